@@ -0,0 +1,360 @@
+// `#[derive(FromValue, IntoValue)]` for structs and enums, as compounds:
+//
+//     #[derive(FromValue, IntoValue)]
+//     #[compound("point")]
+//     pub struct Point { pub x: i64, pub y: i64 }
+//
+//     #[derive(FromValue, IntoValue)]
+//     pub enum Shape { #[compound("circle")] Circle { at: Point, r: i64 }, Empty }
+//
+// so that `point(1, 2)` is a Point, and `empty` is Shape::Empty. A struct or variant without
+// `#[compound(..)]` gets its name in snake_case as its head. Fields can be named, unnamed or
+// missing; either way they're the compound's args, in order.
+//
+// No syn or quote: the input is walked by hand, so generics and where clauses aren't supported.
+// The output names everything through `::snapshots`, which the crate itself makes work
+// with `extern crate self as snapshots`.
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+#[proc_macro_derive(FromValue, attributes(compound))]
+pub fn derive_from_value(input: TokenStream) -> TokenStream {
+    match parse(input) {
+        Ok(item) => emit(&from_value(&item)),
+        Err(message) => error(&message),
+    }
+}
+
+#[proc_macro_derive(IntoValue, attributes(compound))]
+pub fn derive_into_value(input: TokenStream) -> TokenStream {
+    match parse(input) {
+        Ok(item) => emit(&into_value(&item)),
+        Err(message) => error(&message),
+    }
+}
+
+struct Item {
+    name: String,
+    body: Body,
+}
+
+enum Body {
+    Struct(Variant),
+    Enum(Vec<Variant>),
+}
+
+struct Variant {
+    name: String,
+    head: String,
+    fields: Fields,
+}
+
+enum Fields {
+    Unit,
+    Named(Vec<(String, String)>), // name, type
+    Unnamed(Vec<String>), // type
+}
+
+impl Fields {
+    fn types(&self) -> Vec<String> {
+        match self {
+            Fields::Unit => vec![],
+            Fields::Named(fields) => fields.iter().map(|(_, t)| t.clone()).collect(),
+            Fields::Unnamed(types) => types.clone(),
+        }
+    }
+
+    // what the fields are bound to while they're converted (not their own names, which could be `interns`)
+    fn bindings(&self) -> Vec<String> {
+        (0..self.types().len()).map(|i| format!("field{}", i)).collect()
+    }
+}
+
+type Tokens = Peekable<IntoIter<TokenTree>>;
+
+fn parse(input: TokenStream) -> Result<Item, String> {
+    let mut tokens: Tokens = input.into_iter().collect::<Vec<_>>().into_iter().peekable();
+    let head = attributes(&mut tokens)?;
+    visibility(&mut tokens);
+
+    let keyword = ident(&mut tokens)?;
+    let name = ident(&mut tokens)?;
+    if is_punct(tokens.peek(), '<') {
+        return Err(format!("{} is generic: FromValue and IntoValue only derive for plain types", name));
+    }
+
+    let body = match keyword.as_str() {
+        "struct" => {
+            let fields = match tokens.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => named(g.stream())?,
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => unnamed(g.stream())?,
+                _ => Fields::Unit,
+            };
+            Body::Struct(Variant { head: head.unwrap_or_else(|| snake_case(&name)), name: name.clone(), fields })
+        }
+        "enum" => {
+            if head.is_some() {
+                return Err(format!("{}: #[compound(..)] goes on each variant of an enum", name));
+            }
+            match tokens.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => Body::Enum(variants(g.stream())?),
+                _ => { return Err(format!("{}: expected the enum's variants", name)); }
+            }
+        }
+        other => { return Err(format!("FromValue and IntoValue derive for structs and enums, not {}s", other)); }
+    };
+    Ok(Item { name, body })
+}
+
+// the head from `#[compound("head")]`, if it's among them
+fn attributes(tokens: &mut Tokens) -> Result<Option<String>, String> {
+    let mut head = None;
+    while is_punct(tokens.peek(), '#') {
+        tokens.next();
+        let attribute = match tokens.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => g.stream(),
+            _ => { return Err("expected an attribute after #".to_owned()); }
+        };
+        let mut inner = attribute.into_iter();
+        match inner.next() {
+            Some(TokenTree::Ident(i)) if i.to_string() == "compound" => {}
+            _ => continue,
+        }
+        let args: Vec<TokenTree> = match inner.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => g.stream().into_iter().collect(),
+            _ => vec![],
+        };
+        match args.as_slice() {
+            [TokenTree::Literal(l)] if l.to_string().starts_with('"') => {
+                head = Some(l.to_string().trim_matches('"').to_owned());
+            }
+            _ => { return Err("expected #[compound(\"head\")]".to_owned()); }
+        }
+    }
+    Ok(head)
+}
+
+fn visibility(tokens: &mut Tokens) {
+    if let Some(TokenTree::Ident(i)) = tokens.peek() {
+        if i.to_string() == "pub" {
+            tokens.next();
+            if let Some(TokenTree::Group(g)) = tokens.peek() {
+                if g.delimiter() == Delimiter::Parenthesis { tokens.next(); }
+            }
+        }
+    }
+}
+
+fn ident(tokens: &mut Tokens) -> Result<String, String> {
+    match tokens.next() {
+        Some(TokenTree::Ident(i)) => Ok(i.to_string()),
+        other => Err(format!("expected a name, got {}", other.map_or("nothing".to_owned(), |t| t.to_string()))),
+    }
+}
+
+fn is_punct(token: Option<&TokenTree>, c: char) -> bool {
+    match token {
+        Some(TokenTree::Punct(p)) => p.as_char() == c,
+        _ => false,
+    }
+}
+
+// Splits on the commas that aren't inside a type's <..>: those aren't groups, so they're
+// counted by hand (and the > of an -> doesn't count)
+fn split_commas(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![vec![]];
+    let mut depth = 0;
+    let mut arrow = false;
+    for token in stream {
+        if let TokenTree::Punct(p) = &token {
+            match p.as_char() {
+                ',' if depth == 0 => { parts.push(vec![]); continue; }
+                '<' => { depth += 1; }
+                '>' if !arrow => { depth -= 1; }
+                _ => {}
+            }
+            arrow = p.as_char() == '-' && p.spacing() == Spacing::Joint;
+        } else {
+            arrow = false;
+        }
+        parts.last_mut().unwrap().push(token);
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn named(stream: TokenStream) -> Result<Fields, String> {
+    let mut fields = vec![];
+    for part in split_commas(stream) {
+        let mut tokens: Tokens = part.into_iter().collect::<Vec<_>>().into_iter().peekable();
+        attributes(&mut tokens)?;
+        visibility(&mut tokens);
+        let name = ident(&mut tokens)?;
+        if !is_punct(tokens.next().as_ref(), ':') {
+            return Err(format!("expected a type for {}", name));
+        }
+        fields.push((name, tokens.collect::<TokenStream>().to_string()));
+    }
+    Ok(Fields::Named(fields))
+}
+
+fn unnamed(stream: TokenStream) -> Result<Fields, String> {
+    let mut types = vec![];
+    for part in split_commas(stream) {
+        let mut tokens: Tokens = part.into_iter().collect::<Vec<_>>().into_iter().peekable();
+        attributes(&mut tokens)?;
+        visibility(&mut tokens);
+        types.push(tokens.collect::<TokenStream>().to_string());
+    }
+    Ok(Fields::Unnamed(types))
+}
+
+fn variants(stream: TokenStream) -> Result<Vec<Variant>, String> {
+    let mut variants = vec![];
+    for part in split_commas(stream) {
+        let mut tokens: Tokens = part.into_iter().collect::<Vec<_>>().into_iter().peekable();
+        let head = attributes(&mut tokens)?;
+        let name = ident(&mut tokens)?;
+        let fields = match tokens.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => named(g.stream())?,
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => unnamed(g.stream())?,
+            None => Fields::Unit,
+            Some(_) => { return Err(format!("{}: explicit discriminants aren't supported", name)); }
+        };
+        variants.push(Variant { head: head.unwrap_or_else(|| snake_case(&name)), name, fields });
+    }
+    Ok(variants)
+}
+
+// `ShapeKind` to `shape_kind`
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 { out.push('_'); }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+const TYPED: &str = "::snapshots::library::typed";
+const INTERNS: &str = "::snapshots::interns::Interns";
+const VALUE: &str = "::snapshots::primitive::Value";
+const NATIVE_ERROR: &str = "::snapshots::errors::runtime::NativeError";
+
+fn from_value(item: &Item) -> String {
+    let body = match &item.body {
+        Body::Struct(v) => format!(
+            "#[allow(unused_mut, unused_variables)] let mut args = {t}::compound_args(interns, {h:?}, {n}, value)?.into_iter(); Ok({c})",
+            t = TYPED, h = v.head, n = v.fields.types().len(), c = construct(&item.name, v),
+        ),
+        Body::Enum(variants) => {
+            let mut body = String::new();
+            for v in variants {
+                body += &format!(
+                    "if {t}::is_compound(interns, {h:?}, {n}, &value) {{ \
+                        #[allow(unused_mut, unused_variables)] let mut args = {t}::compound_args(interns, {h:?}, {n}, value)?.into_iter(); \
+                        return Ok({c}); \
+                    }}",
+                    t = TYPED, h = v.head, n = v.fields.types().len(),
+                    c = construct(&format!("{}::{}", item.name, v.name), v),
+                );
+            }
+            let heads: Vec<&str> = variants.iter().map(|v| v.head.as_str()).collect();
+            body + &format!("Err({}::new({:?}))", NATIVE_ERROR, format!("expected one of {}", heads.join(", ")))
+        }
+    };
+    format!(
+        "impl {t}::FromValue for {name} {{ \
+            fn from_value(interns: &{i}, value: {v}) -> Result<Self, {e}> {{ {body} }} \
+        }}",
+        t = TYPED, name = item.name, i = INTERNS, v = VALUE, e = NATIVE_ERROR, body = body,
+    )
+}
+
+// `path` built from `args`, one converted arg per field
+fn construct(path: &str, v: &Variant) -> String {
+    let arg = |what: &str| format!("{}::nth(interns, {:?}, args.next().unwrap())?", TYPED, what);
+    match &v.fields {
+        Fields::Unit => path.to_owned(),
+        Fields::Named(fields) => {
+            let fields: Vec<String> = fields.iter().map(|(n, _)| format!("{}: {}", n, arg(n))).collect();
+            format!("{} {{ {} }}", path, fields.join(", "))
+        }
+        Fields::Unnamed(types) => {
+            let args: Vec<String> = (0..types.len()).map(|i| arg(&format!("arg {}", i))).collect();
+            format!("{}({})", path, args.join(", "))
+        }
+    }
+}
+
+fn into_value(item: &Item) -> String {
+    let (body, variants): (String, Vec<&Variant>) = match &item.body {
+        Body::Struct(v) => (format!("let {} = self; {}", pattern(&item.name, v), compound(v)), vec![v]),
+        Body::Enum(variants) => {
+            let arms: Vec<String> = variants.iter()
+                .map(|v| format!("{} => {{ {} }}", pattern(&format!("{}::{}", item.name, v.name), v), compound(v)))
+                .collect();
+            (format!("match self {{ {} }}", arms.join(" ")), variants.iter().collect())
+        }
+    };
+
+    let mut prepare = String::new();
+    for v in variants {
+        prepare += &format!("interns.intern({:?});", v.head);
+        for t in v.fields.types() {
+            prepare += &format!("<{} as {}::IntoValue>::prepare_with(interns, prepared);", t, TYPED);
+        }
+    }
+
+    format!(
+        "impl {t}::IntoValue for {name} {{ \
+            fn into_value(self, interns: &{i}) -> Result<{v}, {e}> {{ {body} }} \
+            fn prepare_with(interns: &mut {i}, prepared: &mut {t}::Prepared) {{ \
+                if !prepared.first::<Self>() {{ return; }} \
+                {prepare} \
+            }} \
+        }}",
+        t = TYPED, name = item.name, i = INTERNS, v = VALUE, e = NATIVE_ERROR, body = body, prepare = prepare,
+    )
+}
+
+// binds every field of `path` to its name in `bindings`
+fn pattern(path: &str, v: &Variant) -> String {
+    match &v.fields {
+        Fields::Unit => path.to_owned(),
+        Fields::Named(fields) => {
+            let fields: Vec<String> = fields.iter().zip(v.fields.bindings())
+                .map(|((n, _), b)| format!("{}: {}", n, b))
+                .collect();
+            format!("{} {{ {} }}", path, fields.join(", "))
+        }
+        Fields::Unnamed(_) => format!("{}({})", path, v.fields.bindings().join(", ")),
+    }
+}
+
+// the compound for a variant whose fields are bound
+fn compound(v: &Variant) -> String {
+    let args: Vec<String> = v.fields.bindings().iter()
+        .map(|b| format!("{}::IntoValue::into_value({}, interns)?", TYPED, b))
+        .collect();
+    format!(
+        "let head = {t}::head(interns, {h:?})?; Ok({v}::compound(head, vec![{args}]))",
+        t = TYPED, h = v.head, v = VALUE, args = args.join(", "),
+    )
+}
+
+fn emit(code: &str) -> TokenStream {
+    code.parse().unwrap_or_else(|e| error(&format!("couldn't derive: {:?}", e)))
+}
+
+fn error(message: &str) -> TokenStream {
+    format!("compile_error!({:?});", message).parse().unwrap()
+}
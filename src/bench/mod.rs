@@ -0,0 +1,61 @@
+use crate::check::compile_file;
+use crate::compiler::Options;
+use crate::interns::{Intern, Interns};
+use crate::irs::executable1::{Executable1, FFIProcedure};
+use crate::irs::procedure1::OptLevel;
+use crate::primitive::{Functor, Value};
+use crate::vm::VM;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const MIN_RUNS: u32 = 5;
+const MIN_TIME: Duration = Duration::from_millis(500);
+
+// Times every public procedure with no args in each file (see programs/bench).
+// Build with --release or the numbers don't mean much. False if a file didn't compile.
+pub fn bench(paths: &[impl AsRef<Path>], opt: OptLevel) -> bool {
+    let mut all_compiled = true;
+    for path in paths {
+        let path = path.as_ref();
+        let mut interns = Interns::new(0);
+        let code = match compile_file(path, &mut interns, Options { opt, ..Options::default() }) {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("{}", e);
+                all_compiled = false;
+                continue;
+            }
+        };
+
+        let mut entries: Vec<Functor<Intern>> = code.procedures.iter()
+            .filter_map(|(f, p)| match p {
+                FFIProcedure::Dynamic(p) if p.public && f.1 == 0 => Some(*f),
+                _ => None,
+            })
+            .collect();
+        entries.sort();
+
+        for entry in entries {
+            let name = interns.to_string(entry.0).unwrap_or("?").to_owned();
+            let mut runs = 0;
+            let start = Instant::now();
+            while runs < MIN_RUNS || start.elapsed() < MIN_TIME {
+                run(&interns, &code, entry);
+                runs += 1;
+            }
+            let each = start.elapsed() / runs;
+            println!("{} {}: {:?} per run ({} runs)", path.display(), name, each, runs);
+        }
+    }
+    all_compiled
+}
+
+fn run(interns: &Interns, code: &Executable1, entry: Functor<Intern>) {
+    let call = Value::compound(entry.0, vec![]);
+    let mut vm = VM::go(interns, code, &mut (), call).unwrap();
+    while vm.is_running() {
+        vm.run(interns, &mut (), usize::MAX);
+    }
+    if let VM::Failed(e) = vm { panic!("{:?}", e); }
+}
@@ -0,0 +1,127 @@
+use crate::compiler::{compile_modules, Options};
+use crate::errors::loader::Loading;
+use crate::interns::{Intern, Interns};
+use crate::irs::ast1::Module;
+use crate::irs::executable1::{Executable1, FFIProcedure};
+use crate::irs::procedure1::OptLevel;
+use crate::library;
+use crate::loader::Loader;
+use crate::primitive::{Functor, Value};
+use crate::store::Store;
+use crate::vm::VM;
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+// a file and its imports, with the file's directory as the search path
+pub fn load(path: &Path) -> Loading<Vec<Module>> {
+    let dir = path.parent().map(|p| p.to_owned()).unwrap_or_default();
+    Loader::new(vec![dir]).load(path)
+}
+
+// load, then compile, with whatever went wrong ready to print
+pub fn compile_file(path: &Path, interns: &mut Interns, options: Options) -> Result<Executable1, String> {
+    let modules = load(path).map_err(|e| e.to_string())?;
+    compile_modules(modules, interns, library::Standard, options)
+        .map_err(|e| format!("{}: doesn't compile: {:?}", path.display(), e))
+}
+
+// The optimizer's test suite: runs every public procedure with no args at -O0 and at -O1
+// (see programs/opt for the ones it's meant for), and complains about any that disagree.
+pub fn check_opt(paths: &[impl AsRef<Path>]) -> bool {
+    let mut all_agree = true;
+    for path in paths {
+        let path = path.as_ref();
+        let mut interns = Interns::new(0);
+        let compiled = compile_file(path, &mut interns, Options { opt: OptLevel::O0, ..Options::default() })
+            .and_then(|unoptimized| {
+                let optimized = compile_file(path, &mut interns, Options { opt: OptLevel::O1, ..Options::default() })?;
+                Ok((unoptimized, optimized))
+            });
+        let (unoptimized, optimized) = match compiled {
+            Ok(compiled) => compiled,
+            Err(e) => {
+                println!("FAIL {}", e);
+                all_agree = false;
+                continue;
+            }
+        };
+
+        let mut entries: Vec<Functor<Intern>> = unoptimized.procedures.iter()
+            .filter_map(|(f, p)| match p {
+                FFIProcedure::Dynamic(p) if p.public && f.1 == 0 => Some(*f),
+                _ => None,
+            })
+            .collect();
+        entries.sort();
+
+        for entry in entries {
+            let name = interns.to_string(entry.0).unwrap_or("?").to_owned();
+            let expected = run(&interns, &unoptimized, entry);
+            let found = run(&interns, &optimized, entry);
+            if expected == found {
+                println!("ok   {} {}: {}", path.display(), name, expected);
+            } else {
+                println!("FAIL {} {}: {} at -O0, {} at -O1", path.display(), name, expected, found);
+                all_agree = false;
+            }
+        }
+        println!(
+            "     {}: {} instructions at -O0, {} at -O1",
+            path.display(), size(&unoptimized), size(&optimized),
+        );
+    }
+    all_agree
+}
+
+fn run(interns: &Interns, code: &Executable1, entry: Functor<Intern>) -> String {
+    let call = Value::compound(entry.0, vec![]);
+    let mut vm = match VM::go(interns, code, &mut (), call) {
+        Ok(vm) => vm,
+        Err(e) => { return format!("failed: {:?}", e); }
+    };
+    // a fresh one for every run, so -O0 and -O1 start from the same place
+    vm.use_store(Rc::new(RefCell::new(Store::new())));
+    loop {
+        while vm.is_running() {
+            vm.run(interns, &mut (), usize::MAX);
+        }
+        // every yield gets back what it yielded
+        match &vm {
+            VM::Yielded(_, value) => { let answer = value.clone(); vm.resume(answer); }
+            _ => break,
+        }
+    }
+    match vm {
+        VM::Succeeded(value, _) => format!("{:?}", value),
+        VM::Failed(e) => format!("failed: {:?}", e),
+        _ => unreachable!(),
+    }
+}
+
+fn size(code: &Executable1) -> usize {
+    code.procedures.values()
+        .map(|p| match p {
+            FFIProcedure::Dynamic(p) => p.instructions.len(),
+            FFIProcedure::Native(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[test]
+    fn optimizer_agrees() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("programs/opt");
+        let mut paths: Vec<_> = dir.read_dir().unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "pter"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        assert!(super::check_opt(&paths));
+    }
+}
@@ -10,7 +10,7 @@ use ast1::Block;
 
 impl Block {
     pub fn compile(self, it: &mut Interns, pp: &mut Procedure1) -> Compiler<()> {
-        for st in self.0 {
+        for (_, st) in self.0 {
             st.compile(it, pp)?;
         };
         Ok(())
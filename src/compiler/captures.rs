@@ -0,0 +1,119 @@
+use crate::irs::ast1;
+use crate::irs::procedure1;
+
+use std::collections::BTreeSet;
+
+use ast1::{Block, Condition, Expression, Pattern, Statement};
+use procedure1::Procedure1;
+
+// The variables a lambda closes over: anything it mentions that the enclosing procedure
+// has already allocated, except what its own args bind.
+// (Sorted, so that the layout of the closure compound doesn't depend on hashing.)
+pub fn captures(args: &[Pattern], body: &Block, pp: &Procedure1) -> Vec<String> {
+    let mut bound = BTreeSet::new();
+    for arg in args { in_pattern(arg, &mut bound); }
+
+    let mut mentioned = BTreeSet::new();
+    in_block(body, &mut mentioned);
+
+    mentioned.into_iter()
+        .filter(|v| !bound.contains(v) && pp.local_name_to_ix().contains_key(v))
+        .collect()
+}
+
+fn in_block(bl: &Block, into: &mut BTreeSet<String>) {
+    for (_, st) in bl.0.iter() { in_statement(st, into); }
+}
+
+fn in_statement(st: &Statement, into: &mut BTreeSet<String>) {
+    match st {
+        Statement::If(cond, bl_then, obl_else) => {
+            match cond {
+                Condition::Let(lhs, rhs) => {
+                    in_pattern(lhs, into);
+                    in_expression(rhs, into);
+                }
+                Condition::Bare(xp) => in_expression(xp, into),
+            }
+            in_block(bl_then, into);
+            if let Some(bl_else) = obl_else { in_block(bl_else, into); }
+        }
+        Statement::Assign(lhs, rhs) => {
+            into.insert(lhs.clone());
+            in_expression(rhs, into);
+        }
+        Statement::VecPush(lhs, rhs) | Statement::SetArg(lhs, _, rhs) => {
+            into.insert(lhs.clone());
+            in_expression(rhs, into);
+        }
+        Statement::VecSet(lhs, index, rhs) => {
+            into.insert(lhs.clone());
+            in_expression(index, into);
+            in_expression(rhs, into);
+        }
+        Statement::Destructure(lhs, rhs) => {
+            in_pattern(lhs, into);
+            in_expression(rhs, into);
+        }
+        Statement::Match(scrutinee, arms) => {
+            in_expression(scrutinee, into);
+            for (pat, bl) in arms.iter() {
+                in_pattern(pat, into);
+                in_block(bl, into);
+            }
+        }
+        Statement::Send(pid, msg) => {
+            in_expression(pid, into);
+            in_expression(msg, into);
+        }
+        Statement::Receive(arms) => {
+            for (pat, bl) in arms.iter() {
+                in_pattern(pat, into);
+                in_block(bl, into);
+            }
+        }
+        Statement::Atomic(bl) => in_block(bl, into),
+        Statement::Abort => {}
+        Statement::StorePut(key, value) => {
+            in_expression(key, into);
+            in_expression(value, into);
+        }
+        Statement::Eval(xp) => in_expression(xp, into),
+        Statement::Ret(xp) => in_expression(xp, into),
+    }
+}
+
+fn in_expression(xp: &Expression, into: &mut BTreeSet<String>) {
+    match xp {
+        Expression::NoOp | Expression::IntLiteral(_) | Expression::SelfPid => {}
+        Expression::Variable(n) | Expression::VecPop(n) => { into.insert(n.clone()); }
+        Expression::Call(box e) | Expression::Spawn(box e) | Expression::Yield(box e) |
+        Expression::StoreGet(box e) => in_expression(e, into),
+        Expression::Apply(box e, args) => {
+            in_expression(e, into);
+            for arg in args { in_expression(arg, into); }
+        }
+        Expression::Lambda(args, body) => {
+            // a nested lambda's captures have to pass through this one
+            for arg in args { in_pattern(arg, into); }
+            in_block(body, into);
+        }
+        Expression::Compound(_, args) => for arg in args { in_expression(arg, into); },
+        Expression::Vector(args) => for arg in args { in_expression(arg, into); },
+        Expression::Set(args) => for arg in args { in_expression(arg, into); },
+        Expression::Binary(box lhs, _, box rhs) => {
+            in_expression(lhs, into);
+            in_expression(rhs, into);
+        }
+    }
+}
+
+fn in_pattern(pat: &Pattern, into: &mut BTreeSet<String>) {
+    match pat {
+        Pattern::IntLiteral(_) => {}
+        Pattern::Variable(n) => { into.insert(n.clone()); }
+        Pattern::Compound(_, args) => for arg in args { in_pattern(arg, into); },
+        Pattern::WcCompound(args) => for arg in args { in_pattern(arg, into); },
+        Pattern::Vector(args) => for arg in args { in_pattern(arg, into); },
+    }
+}
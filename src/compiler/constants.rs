@@ -0,0 +1,149 @@
+use crate::interns::Interns;
+use crate::irs::ast1;
+use crate::irs::procedure1::{qualify, Scope};
+use crate::primitive::Value;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::errors::compiler::*;
+
+use ast1::{BinOp, Expression, Module};
+
+// qualified name -> index into the constant pool, in declaration order: every const, and then
+// every global's starting value. Both are written like atoms, so they can't share a name
+pub fn constant_names(modules: &[Module]) -> Compiler<(HashMap<String, usize>, HashMap<String, usize>)> {
+    let mut names = HashMap::new();
+    for module in modules {
+        for c in module.consts.iter() {
+            let name = qualify(&module.name, &c.name);
+            let ix = names.len();
+            if names.insert(name.clone(), ix).is_some() {
+                return Err(Error::DuplicatedConstant(name));
+            }
+        }
+    }
+    let mut globals = HashMap::new();
+    for module in modules {
+        for g in module.globals.iter() {
+            let name = qualify(&module.name, &g.name);
+            let ix = names.len() + globals.len();
+            if names.contains_key(&name) || globals.insert(name.clone(), ix).is_some() {
+                return Err(Error::DuplicatedConstant(name));
+            }
+        }
+    }
+    Ok((names, globals))
+}
+
+// Consts are evaluated on demand, so they can refer to each other in any order (but not in a cycle).
+pub fn evaluate_constants(
+    it: &mut Interns, modules: &[Module], scopes: &[Rc<Scope>],
+) -> Compiler<Vec<Value>> {
+    let mut decls = vec![];
+    for (module, scope) in modules.iter().zip(scopes.iter()) {
+        for c in module.consts.iter() {
+            decls.push((scope.qualify(&c.name), &c.value, &**scope));
+        }
+    }
+    for (module, scope) in modules.iter().zip(scopes.iter()) {
+        for g in module.globals.iter() {
+            decls.push((scope.qualify(&g.name), &g.value, &**scope));
+        }
+    }
+
+    let mut evaluator = Evaluator {
+        it,
+        values: decls.iter().map(|_| None).collect(),
+        evaluating: decls.iter().map(|_| false).collect(),
+        decls,
+    };
+    for ix in 0..evaluator.decls.len() {
+        evaluator.constant(ix)?;
+    }
+    Ok(evaluator.values.into_iter().map(|v| v.unwrap()).collect())
+}
+
+struct Evaluator<'m, 'i, 'proto> {
+    it: &'i mut Interns<'proto>,
+    decls: Vec<(String, &'m Expression, &'m Scope)>,
+    values: Vec<Option<Value>>,
+    evaluating: Vec<bool>,
+}
+
+impl<'m, 'i, 'proto> Evaluator<'m, 'i, 'proto> {
+    fn constant(&mut self, ix: usize) -> Compiler<Value> {
+        if let Some(v) = &self.values[ix] { return Ok(v.clone()); }
+        if self.evaluating[ix] { return Err(Error::RecursiveConstant(self.decls[ix].0.clone())); }
+
+        self.evaluating[ix] = true;
+        let (_, xp, scope) = self.decls[ix];
+        let value = self.expression(ix, xp, scope)?;
+        self.values[ix] = Some(value.clone());
+        Ok(value)
+    }
+
+    // `ix` is the const being evaluated, for errors
+    fn expression(&mut self, ix: usize, xp: &Expression, scope: &Scope) -> Compiler<Value> {
+        Ok(match xp {
+            Expression::IntLiteral(i) => Value::Integer(*i),
+            Expression::Compound(name, args) if args.is_empty() => {
+                match scope.constant(name)? {
+                    Some(c) => self.constant(c)?,
+                    None => Value::compound(self.it.intern(name), vec![]),
+                }
+            }
+            Expression::Compound(name, args) => {
+                let mut values = vec![];
+                for arg in args { values.push(self.expression(ix, arg, scope)?); }
+                Value::compound(self.it.intern(name), values)
+            }
+            Expression::Vector(args) => {
+                let mut values = vec![];
+                for arg in args { values.push(self.expression(ix, arg, scope)?); }
+                Value::vector(values)
+            }
+            Expression::Set(args) => {
+                let mut values = vec![];
+                for arg in args { values.push(self.expression(ix, arg, scope)?); }
+                Value::set(values.into_iter().collect())
+            }
+            Expression::Binary(box lhs, op, box rhs) => {
+                let lhs = self.expression(ix, lhs, scope)?;
+                let rhs = self.expression(ix, rhs, scope)?;
+                match binary(*op, lhs, rhs) {
+                    Some(v) => v,
+                    None => { return Err(Error::BadConstant(self.decls[ix].0.clone())); }
+                }
+            }
+            Expression::NoOp | Expression::Variable(_) | Expression::VecPop(_) | Expression::Call(_) |
+            Expression::Spawn(_) | Expression::SelfPid | Expression::Yield(_) | Expression::StoreGet(_) |
+            Expression::Apply(_, _) | Expression::Lambda(_, _) => {
+                return Err(Error::NotConstant(self.decls[ix].0.clone()));
+            }
+        })
+    }
+}
+
+// the same as the VM, except that overflow and dividing by zero are errors instead of panics
+fn binary(op: BinOp, lhs: Value, rhs: Value) -> Option<Value> {
+    let (i1, i2) = match (lhs, rhs) {
+        (Value::Integer(i1), Value::Integer(i2)) => (i1, i2),
+        _ => { return None; }
+    };
+    Some(match op {
+        BinOp::And | BinOp::Or => { return None; } // can't compile these yet either
+
+        BinOp::Multiply => Value::Integer(i1.checked_mul(i2)?),
+        BinOp::Divide => Value::Integer(i1.checked_div(i2)?),
+        BinOp::Add => Value::Integer(i1.checked_add(i2)?),
+        BinOp::Subtract => Value::Integer(i1.checked_sub(i2)?),
+
+        BinOp::Le => Value::Bool(i1 <= i2),
+        BinOp::Ge => Value::Bool(i1 >= i2),
+        BinOp::Lt => Value::Bool(i1 < i2),
+        BinOp::Gt => Value::Bool(i1 > i2),
+        BinOp::Eq => Value::Bool(i1 == i2),
+        BinOp::Ne => Value::Bool(i1 != i2),
+    })
+}
@@ -0,0 +1,381 @@
+use crate::interns::{Intern, Interns};
+use crate::irs::ast1;
+use crate::irs::procedure1::Scope;
+use crate::primitive::Functor;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::errors::compiler::*;
+
+use ast1::{Block, Condition, Expression, Pattern, Procedure, Statement, TypeDecl, TypeExpr};
+
+const BUILTIN_TYPES: &[&str] = &["int", "bool", "any"];
+
+// Type declarations are optional. When a module has none, compounds are left unchecked.
+// When it has some:
+// - a declared constructor must always be used at its declared arity
+// - any other compound with args must be a procedure functor (so that it can be called)
+// - nullary compounds that aren't constructors are plain atoms, and always allowed
+// - a match whose arms use a declared type's constructors must cover every constructor, unless
+//   whether one of its variables is already bound depends on the path taken to the match
+pub struct Declarations {
+    constructors: HashMap<String, (String, usize)>, // constructor -> type, arity
+    variants: HashMap<String, Vec<String>>, // type -> constructors, in declaration order
+}
+
+impl Declarations {
+    pub fn new(types: &[TypeDecl]) -> Compiler<Declarations> {
+        let mut decls = Declarations {
+            constructors: HashMap::new(),
+            variants: HashMap::new(),
+        };
+
+        for t in types {
+            if BUILTIN_TYPES.contains(&t.name.as_str()) || decls.variants.contains_key(&t.name) {
+                return Err(Error::DuplicatedType(t.name.clone()));
+            }
+            let mut names = vec![];
+            for v in t.variants.iter() {
+                if decls.constructors.contains_key(&v.name) {
+                    return Err(Error::DuplicatedConstructor(v.name.clone()));
+                }
+                decls.constructors.insert(v.name.clone(), (t.name.clone(), v.args.len()));
+                names.push(v.name.clone());
+            }
+            decls.variants.insert(t.name.clone(), names);
+        }
+
+        // types can refer to each other in any order, so check names once they're all known
+        for t in types {
+            for v in t.variants.iter() {
+                for arg in v.args.iter() { decls.check_type_expr(arg)?; }
+            }
+        }
+
+        Ok(decls)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+
+    pub fn is_constructor(&self, name: &str) -> bool {
+        self.constructors.contains_key(name)
+    }
+
+    pub fn check_procedure(
+        &self, interns: &Interns, callable: &HashSet<Functor<Intern>>, scope: &Scope, procedure: &Procedure,
+    ) -> Compiler<()> {
+        let mut checker = Checker { decls: self, interns, callable, scope, bound: HashSet::new(), maybe: HashSet::new() };
+        for arg in procedure.args.iter() {
+            checker.pattern(arg)?;
+        }
+        checker.block(&procedure.body)
+    }
+
+    fn check_type_expr(&self, t: &TypeExpr) -> Compiler<()> {
+        match t {
+            TypeExpr::Named(n) => {
+                if BUILTIN_TYPES.contains(&n.as_str()) || self.variants.contains_key(n) { Ok(()) }
+                else { Err(Error::UnknownType(n.clone())) }
+            }
+            TypeExpr::Vector(box t) => self.check_type_expr(t),
+            TypeExpr::Set(box t) => self.check_type_expr(t),
+        }
+    }
+}
+
+struct Checker<'d> {
+    decls: &'d Declarations,
+    interns: &'d Interns<'d>,
+    callable: &'d HashSet<Functor<Intern>>,
+    scope: &'d Scope,
+
+    // variables that are bound on every path to here: a variable pattern on one of these is an equality test
+    bound: HashSet<String>,
+    // variables that are bound on some path to here: only a variable pattern on none of these always matches.
+    // one that's in here and not in `bound` could go either way, so the match isn't checked
+    maybe: HashSet<String>,
+}
+
+impl<'d> Checker<'d> {
+    fn block(&mut self, bl: &Block) -> Compiler<()> {
+        for (_, st) in bl.0.iter() {
+            self.statement(st)?;
+        }
+        Ok(())
+    }
+
+    // one way control can go: returns what's definitely bound at the end of it, and puts `bound` back
+    fn branch(&mut self, f: impl FnOnce(&mut Self) -> Compiler<()>) -> Compiler<HashSet<String>> {
+        let before = self.bound.clone();
+        f(self)?;
+        Ok(std::mem::replace(&mut self.bound, before))
+    }
+
+    // after one of several branches: what all of them bound
+    fn join(&mut self, branches: Vec<HashSet<String>>) {
+        let mut branches = branches.into_iter();
+        if let Some(first) = branches.next() {
+            self.bound = branches.fold(first, |all, b| all.intersection(&b).cloned().collect());
+        }
+    }
+
+    fn bind(&mut self, name: &str) {
+        self.bound.insert(name.to_owned());
+        self.maybe.insert(name.to_owned());
+    }
+
+    fn statement(&mut self, st: &Statement) -> Compiler<()> {
+        match st {
+            Statement::If(cond, bl_then, obl_else) => {
+                // a `let` that fails partway can leave some of its variables bound in the else branch
+                let then = match cond {
+                    Condition::Let(lhs, rhs) => {
+                        self.expression(rhs)?;
+                        self.branch(|c| { c.pattern(lhs)?; c.block(bl_then) })?
+                    }
+                    Condition::Bare(xp) => {
+                        self.expression(xp)?;
+                        self.branch(|c| c.block(bl_then))?
+                    }
+                };
+                let otherwise = self.branch(|c| match obl_else {
+                    Some(bl_else) => c.block(bl_else),
+                    None => Ok(()),
+                })?;
+                self.join(vec![then, otherwise]);
+            }
+            Statement::Assign(lhs, rhs) => {
+                self.expression(rhs)?;
+                self.bind(lhs);
+            }
+            Statement::VecPush(_, rhs) | Statement::SetArg(_, _, rhs) => { self.expression(rhs)?; }
+            Statement::VecSet(_, index, rhs) => {
+                self.expression(index)?;
+                self.expression(rhs)?;
+            }
+            Statement::Destructure(lhs, rhs) => {
+                self.expression(rhs)?;
+                self.pattern(lhs)?;
+            }
+            Statement::Match(scrutinee, arms) => {
+                self.expression(scrutinee)?;
+                self.exhaustive(arms)?;
+                self.arms(arms)?;
+            }
+            Statement::Send(pid, msg) => {
+                self.expression(pid)?;
+                self.expression(msg)?;
+            }
+            Statement::Receive(arms) => {
+                // a message that no arm matches just stays in the mailbox, so there's nothing to be exhaustive about
+                self.arms(arms)?;
+            }
+            Statement::Atomic(bl) => {
+                // an `abort` skips the rest of it
+                self.branch(|c| c.block(bl))?;
+            }
+            Statement::Abort => {}
+            Statement::StorePut(key, value) => {
+                self.expression(key)?;
+                self.expression(value)?;
+            }
+            Statement::Eval(xp) => { self.expression(xp)?; }
+            Statement::Ret(xp) => { self.expression(xp)?; }
+        }
+        Ok(())
+    }
+
+    fn arms(&mut self, arms: &[(Pattern, Block)]) -> Compiler<()> {
+        let mut ends = vec![];
+        for (pat, bl) in arms.iter() {
+            ends.push(self.branch(|c| { c.pattern(pat)?; c.block(bl) })?);
+        }
+        self.join(ends);
+        Ok(())
+    }
+
+    fn expression(&mut self, xp: &Expression) -> Compiler<()> {
+        match xp {
+            Expression::NoOp => {}
+            Expression::IntLiteral(_) => {}
+            Expression::Variable(_) => {}
+            Expression::VecPop(_) => {}
+            Expression::SelfPid => {}
+            Expression::Call(box e) | Expression::Spawn(box e) | Expression::Yield(box e) |
+            Expression::StoreGet(box e) => { self.expression(e)?; }
+            Expression::Apply(box e, args) => {
+                self.expression(e)?;
+                for arg in args { self.expression(arg)?; }
+            }
+            Expression::Lambda(args, body) => {
+                // its own procedure: what it binds stays in there
+                let outside = (self.bound.clone(), self.maybe.clone());
+                for arg in args { self.pattern(arg)?; }
+                self.block(body)?;
+                self.bound = outside.0;
+                self.maybe = outside.1;
+            }
+            Expression::Compound(name, args) => {
+                self.functor(name, args.len())?;
+                for arg in args { self.expression(arg)?; }
+            }
+            Expression::Vector(args) => { for arg in args { self.expression(arg)?; } }
+            Expression::Set(args) => { for arg in args { self.expression(arg)?; } }
+            Expression::Binary(box lhs, _, box rhs) => {
+                self.expression(lhs)?;
+                self.expression(rhs)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn pattern(&mut self, pat: &Pattern) -> Compiler<()> {
+        match pat {
+            Pattern::IntLiteral(_) => {}
+            Pattern::Variable(n) => { self.bind(n); }
+            Pattern::Compound(name, args) => {
+                self.functor(name, args.len())?;
+                for arg in args { self.pattern(arg)?; }
+            }
+            Pattern::WcCompound(args) => { for arg in args { self.pattern(arg)?; } }
+            Pattern::Vector(args) => { for arg in args { self.pattern(arg)?; } }
+        }
+        Ok(())
+    }
+
+    fn functor(&self, name: &str, arity: usize) -> Compiler<()> {
+        if self.is_callable(name, arity) || self.is_callable(&self.scope.resolve(name, arity)?, arity) {
+            return Ok(());
+        }
+
+        match self.decls.constructors.get(name) {
+            Some((_, declared)) if *declared == arity => Ok(()),
+            Some(_) => Err(Error::WrongArity(name.to_owned(), arity)),
+            None if arity == 0 => Ok(()),
+            None => Err(Error::UndeclaredFunctor(name.to_owned(), arity)),
+        }
+    }
+
+    fn is_callable(&self, name: &str, arity: usize) -> bool {
+        match self.interns.to_intern(name) {
+            Some(i) => self.callable.contains(&Functor(i, arity)),
+            None => false,
+        }
+    }
+
+    fn exhaustive(&self, arms: &[(Pattern, Block)]) -> Compiler<()> {
+        let mut matched_type = None;
+        let mut covered = HashSet::new();
+
+        for (pat, _) in arms {
+            match pat {
+                Pattern::Variable(n) if !self.bound.contains(n) => { return Ok(()); } // a catch-all, or might be
+                Pattern::Compound(name, args) => {
+                    if let Some((t, _)) = self.decls.constructors.get(name) {
+                        matched_type = Some(t);
+                        match self.irrefutable_args(args) {
+                            Some(true) => { covered.insert(name.as_str()); }
+                            Some(false) => {}
+                            None => { return Ok(()); }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let t = match matched_type {
+            None => { return Ok(()); } // not a match over a declared type
+            Some(t) => t,
+        };
+        let missing: Vec<String> = self.decls.variants[t].iter()
+            .filter(|v| !covered.contains(v.as_str()))
+            .cloned()
+            .collect();
+
+        if missing.is_empty() { Ok(()) }
+        else { Err(Error::NonExhaustiveMatch(t.clone(), missing)) }
+    }
+
+    // None if that depends on which way control went to get here
+    fn irrefutable_args(&self, args: &[Pattern]) -> Option<bool> {
+        let mut seen = HashSet::new();
+        let mut irrefutable = true;
+        for arg in args {
+            match arg {
+                Pattern::Variable(n) if self.maybe.contains(n) && !self.bound.contains(n) => { return None; }
+                Pattern::Variable(n) if !self.bound.contains(n) && seen.insert(n) => {}
+                _ => { irrefutable = false; }
+            }
+        }
+        Some(irrefutable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::compile;
+
+    const SHAPE: &str = "type shape = circle(int) | rect(int, int) | none.";
+
+    // what compiling `SHAPE` and then `src` comes to
+    fn check(src: &str) -> String {
+        match compile(&[&format!("{} {}", SHAPE, src)]) {
+            Ok(_) => "ok".to_owned(),
+            Err(e) => format!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn declarations() {
+        assert_eq!(check("type shape = square(int)."), "DuplicatedType(\"shape\")");
+        assert_eq!(check("type other = circle(int)."), "DuplicatedConstructor(\"circle\")");
+        assert_eq!(check("type other = wraps(shape) | many(v[s[shape]])."), "ok");
+        assert_eq!(check("type other = wraps(shap)."), "UnknownType(\"shap\")");
+        assert_eq!(check("type other = wraps(v[shap])."), "UnknownType(\"shap\")");
+        assert_eq!(check("pub fn f { ret circle(1, 2). }"), "WrongArity(\"circle\", 2)");
+        assert_eq!(check("pub fn f(circle) { ret 1. }"), "WrongArity(\"circle\", 0)");
+        assert_eq!(check("pub fn f { ret circel(1). }"), "UndeclaredFunctor(\"circel\", 1)");
+        assert_eq!(check("pub fn f(square(@x)) { ret 1. }"), "UndeclaredFunctor(\"square\", 1)");
+        // atoms, and calls as terms, are fine
+        assert_eq!(check("fn g(@x) { ret @x. } pub fn f { let @c = g(1). ret v[anything, @c]. }"), "ok");
+    }
+
+    #[test]
+    fn exhaustive() {
+        let arms = |arms: &str| check(&format!("pub fn f(@s, @r) {{ match @s {{ {} }} ret 0. }}", arms));
+        assert_eq!(arms("circle(@x) => { } rect(@w, @h) => { } none => { }"), "ok");
+        assert_eq!(arms("circle(@x) => { } rect(@w, @h) => { }"), "NonExhaustiveMatch(\"shape\", [\"none\"])");
+        assert_eq!(arms("none => { }"), "NonExhaustiveMatch(\"shape\", [\"circle\", \"rect\"])");
+        // a catch-all covers the rest
+        assert_eq!(arms("circle(@x) => { } @other => { }"), "ok");
+        // but a variable that's already bound is a comparison, not a catch-all
+        assert_eq!(arms("circle(@x) => { } @r => { }"), "NonExhaustiveMatch(\"shape\", [\"rect\", \"none\"])");
+        // an arm with a literal, a bound variable or a repeated one in it doesn't cover its constructor
+        assert_eq!(arms("circle(1) => { } rect(@w, @h) => { } none => { }"), "NonExhaustiveMatch(\"shape\", [\"circle\"])");
+        assert_eq!(arms("circle(@r) => { } rect(@w, @h) => { } none => { }"), "NonExhaustiveMatch(\"shape\", [\"circle\"])");
+        assert_eq!(arms("circle(@x) => { } rect(@w, @w) => { } none => { }"), "NonExhaustiveMatch(\"shape\", [\"rect\"])");
+        // not over a declared type at all
+        assert_eq!(arms("v[@x] => { } 1 => { }"), "ok");
+    }
+
+    #[test]
+    fn bound_on_some_paths() {
+        let src = |before: &str, arm: &str| check(&format!(
+            "pub fn f(@s, @c) {{ {} match @s {{ circle(@r) => {{ }} none => {{ }} {} }} ret 0. }}", before, arm,
+        ));
+        // bound before the match: rect(@x, @y) is a comparison
+        assert_eq!(src("let @x = 1. let @y = 2.", "rect(@x, @y) => { }"), "NonExhaustiveMatch(\"shape\", [\"rect\"])");
+        // bound on every path through the if
+        assert_eq!(src("if @c == 1 { let @x = 1. } else { let @x = 2. }", "@x => { }"), "NonExhaustiveMatch(\"shape\", [\"rect\"])");
+        // bound on only one: it could go either way, so the match isn't checked
+        assert_eq!(src("if @c == 1 { let @x = 1. }", "@x => { }"), "ok");
+        assert_eq!(src("if @c == 1 { let @x = 1. }", "rect(@x, @y) => { }"), "ok");
+        // a lambda's variables stay in the lambda
+        assert_eq!(src("let @g = fn(@x) { ret @x. }.", "@x => { }"), "ok");
+        // unbound everywhere: a catch-all
+        assert_eq!(src("", "rect(@x, @y) => { }"), "ok");
+    }
+}
@@ -1,25 +1,127 @@
 use crate::interns::Interns;
 use crate::irs::ast1;
 use crate::irs::executable1;
+use crate::irs::procedure1::{OptLevel, Pool, Scope};
 use crate::library::Library;
+use crate::primitive::Functor;
+use crate::typer::infer_modules;
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 use crate::errors::compiler::*;
 
+use super::constants::{constant_names, evaluate_constants};
+use super::declarations::Declarations;
+use super::procedure::compile_clauses;
+
 use executable1::{Executable1, FFIProcedure};
 
+// How to compile, besides what to compile
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    pub opt: OptLevel,
+    // run type inference over every module first, and fail on the first type error it finds
+    pub check_types: bool,
+}
+
 impl ast1::Module {
-    pub fn compile(self, interns: &mut Interns, library: impl Library) -> Compiler<Executable1> {
-        let mut procedures = HashMap::new();
+    pub fn compile<H>(self, interns: &mut Interns, library: impl Library<H>) -> Compiler<Executable1<H>> {
+        compile_modules(vec![self], interns, library, Options::default())
+    }
+}
+
+pub fn compile_modules<H>(
+    mut modules: Vec<ast1::Module>, interns: &mut Interns, library: impl Library<H>, options: Options,
+) -> Compiler<Executable1<H>> {
+    let opt = options.opt;
+    let mut procedures = HashMap::new();
+
+    library.add_functions(interns, &mut procedures);
+
+    // claim every functor before compiling anything, so a clash is an error instead of last-one-wins
+    let mut callable: HashSet<_> = procedures.keys().cloned().collect();
+    let (constant_names, global_names) = constant_names(&modules)?;
+    let (constant_names, global_names) = (Rc::new(constant_names), Rc::new(global_names));
+    // consts and globals first, at the indices their names were given, then literals as they're compiled
+    let pool = Rc::new(RefCell::new(Pool::default()));
+    let mut scopes = vec![];
+    for module in modules.iter() {
+        let local = module.procedures.iter().map(|p| (p.name.clone(), p.args.len())).collect();
+        let imports = module.imports.iter().cloned().collect();
+        let scope = Scope::new(
+            module.name.clone(), imports, local,
+            constant_names.clone(), global_names.clone(), pool.clone(),
+        );
+        // several clauses for one functor are fine, as long as they're all in the same module
+        let mut own = HashSet::new();
+        for procedure in module.procedures.iter() {
+            let name = scope.qualify(&procedure.name);
+            let functor = Functor(interns.intern(&name), procedure.args.len());
+            if own.insert(functor) && !callable.insert(functor) {
+                return Err(Error::DuplicatedProcedure(name, procedure.args.len()));
+            }
+        }
+        scopes.push(Rc::new(scope));
+    }
 
-        library.add_functions(interns, &mut procedures);
+    *pool.borrow_mut() = Pool::new(evaluate_constants(interns, &modules, &scopes)?);
 
-        for procedure in self.procedures {
-            let compiled = procedure.compile(interns)?;
-            procedures.insert(compiled.functor, FFIProcedure::Dynamic(compiled));
+    // types aren't namespaced: every module sees every declaration
+    let types: Vec<ast1::TypeDecl> = modules.iter_mut().flat_map(|m| m.types.drain(..)).collect();
+    let inferred = if options.check_types { Some(infer_modules(&modules, &types)?) } else { None };
+    let declarations = Declarations::new(&types)?;
+
+    // a const's name always means the const, so nothing else can go by it
+    for (module, scope) in modules.iter().zip(scopes.iter()) {
+        for c in module.consts.iter() {
+            let name = scope.qualify(&c.name);
+            let procedure = interns.to_intern(&name).map_or(false, |n| callable.contains(&Functor(n, 0)));
+            if procedure || declarations.is_constructor(&c.name) {
+                return Err(Error::ConstantClash(name));
+            }
+        }
+    }
+    if !declarations.is_empty() {
+        for (module, scope) in modules.iter().zip(scopes.iter()) {
+            for procedure in module.procedures.iter() {
+                declarations.check_procedure(interns, &callable, scope, procedure)?;
+            }
+        }
+    }
+
+    for (module, scope) in modules.into_iter().zip(scopes) {
+        for clauses in group_clauses(module.procedures) {
+            for (i, compiled) in compile_clauses(interns, &scope, opt, clauses)?.into_iter().enumerate() {
+                // the procedure itself was claimed up front: the ones lifted out of its lambdas weren't
+                if i > 0 && !callable.insert(compiled.functor) {
+                    let Functor(name, arity) = compiled.functor;
+                    return Err(Error::DuplicatedProcedure(interns.to_string(name).unwrap_or("?").to_owned(), arity));
+                }
+                procedures.insert(compiled.functor, FFIProcedure::Dynamic(compiled));
+            }
         }
+    }
 
-        Ok(Executable1 { procedures })
+    let constants = pool.replace(Pool::default()).into_values();
+    let (constant_names, global_names) = ((*constant_names).clone(), (*global_names).clone());
+    Ok(Executable1 { procedures, constants, constant_names, global_names, types: inferred })
+}
+
+// in order of each procedure's first clause
+fn group_clauses(procedures: Vec<ast1::Procedure>) -> Vec<Vec<ast1::Procedure>> {
+    let mut groups: Vec<Vec<ast1::Procedure>> = vec![];
+    let mut by_functor: HashMap<(String, usize), usize> = HashMap::new();
+    for procedure in procedures {
+        let key = (procedure.name.clone(), procedure.args.len());
+        match by_functor.get(&key) {
+            Some(&ix) => groups[ix].push(procedure),
+            None => {
+                by_functor.insert(key, groups.len());
+                groups.push(vec![procedure]);
+            }
+        }
     }
+    groups
 }
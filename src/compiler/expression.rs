@@ -2,17 +2,59 @@ use crate::interns::Interns;
 use crate::irs::ast1;
 use crate::irs::instruction1;
 use crate::irs::procedure1;
-use crate::primitive::{Functor, Operand};
+use crate::primitive::{Functor, Operand, Value};
+
+use crate::errors::compiler::*;
+
+use super::captures::captures;
+use super::procedure::compile_procedure;
 
 use procedure1::Procedure1;
 
-use ast1::{BinOp, Expression};
+use ast1::{BinOp, Expression, Pattern};
 use instruction1::Instruction1;
 
 impl Expression {
-    pub fn compile(self, it: &mut Interns, pp: &mut Procedure1) {
+    // the target of a call or tail call: a literal compound names a procedure, which may be in this module
+    pub fn compile_callee(self, it: &mut Interns, pp: &mut Procedure1) -> Compiler<()> {
+        match self {
+            Expression::Compound(name, args) => {
+                let resolved = pp.scope().resolve(&name, args.len())?;
+                Expression::Compound(resolved, args).compile(it, pp)
+            }
+            e => e.compile(it, pp),
+        }
+    }
+
+    // A key for the store: a global's name stands for its qualified name, so two modules'
+    // `count`s are different keys. Some(ix) if it was a global, whose starting value is at `ix`
+    pub fn compile_key(self, it: &mut Interns, pp: &mut Procedure1) -> Compiler<Option<usize>> {
+        if let Expression::Compound(name, args) = &self {
+            if let (true, Some((key, ix))) = (args.is_empty(), pp.scope().global(name)?) {
+                let key = it.intern(&key);
+                match pp.operand(Value::compound(key, vec![])) {
+                    Some(operand) => pp.push(Instruction1::Push(operand)),
+                    None => pp.push(Instruction1::ConstructCompound(Functor(key, 0))),
+                }
+                return Ok(Some(ix));
+            }
+        }
+        self.compile(it, pp)?;
+        Ok(None)
+    }
+
+    pub fn compile(self, it: &mut Interns, pp: &mut Procedure1) -> Compiler<()> {
         use Instruction1::*;
         use Expression as E;
+
+        // a literal that comes out the same every time is only built once, at compile time
+        if let E::Compound(..) | E::Vector(..) | E::Set(..) = self {
+            if let Some(operand) = self.ground(it, pp).and_then(|value| pp.operand(value)) {
+                pp.push(Push(operand));
+                return Ok(());
+            }
+        }
+
         match self {
             E::NoOp => {}
 
@@ -24,30 +66,88 @@ impl Expression {
                 pp.push(Get(loc));
             }
             E::Call(box e) => {
-                e.compile(it, pp);
+                e.compile_callee(it, pp)?;
                 pp.push(Instruction1::Call);
             }
+            E::Spawn(box e) => {
+                e.compile_callee(it, pp)?;
+                pp.push(Spawn);
+            }
+            E::SelfPid => {
+                pp.push(SelfPid);
+            }
+            E::Yield(box e) => {
+                e.compile(it, pp)?;
+                pp.push(Yield);
+            }
+            E::StoreGet(box key) => {
+                match key.compile_key(it, pp)? {
+                    Some(ix) => {
+                        pp.push(Push(Operand::Const(ix)));
+                        pp.push(StoreGetOr);
+                    }
+                    None => { pp.push(StoreGet); }
+                }
+            }
+            E::Apply(box e, mut ve) => {
+                let n = ve.len();
+                e.compile(it, pp)?;
+                for i in ve.drain(..) {
+                    i.compile(it, pp)?;
+                }
+                pp.push(ExtendCompound(n));
+            }
+            E::Lambda(args, body) => {
+                // A closure is just a compound: the lifted procedure's name, applied to the captures.
+                // Calling it with more args fills in the rest of the lifted procedure's functor.
+                let captured = captures(&args, &body, pp);
+                let name = pp.lambda_name(it);
+
+                for c in captured.iter() {
+                    let loc = pp.local(c);
+                    pp.push(Get(loc));
+                }
+                pp.push(ConstructCompound(Functor(it.intern(&name), captured.len())));
+
+                let mut lifted_args: Vec<Pattern> = captured.into_iter().map(Pattern::Variable).collect();
+                lifted_args.extend(args);
+                let scope = pp.scope().clone();
+                // private: a host that wants to call a closure has to be handed one by a public procedure
+                let opt = pp.opt_level();
+                let lifted = compile_procedure(it, &scope, opt, &name, false, vec![(lifted_args, body)])?;
+                pp.lift(lifted);
+            }
             E::Compound(s, mut ve) => {
+                if ve.is_empty() {
+                    if let Some(ix) = pp.scope().constant(&s)? {
+                        pp.push(Push(Operand::Const(ix)));
+                        return Ok(());
+                    }
+                }
                 let n = ve.len();
                 for i in ve.drain(..) {
-                    i.compile(it, pp);
+                    i.compile(it, pp)?;
                 }
                 pp.push(ConstructCompound(Functor(it.intern(&s), n)));
             }
             E::Vector(mut ve) => {
                 let n = ve.len();
                 for i in ve.drain(..) {
-                    i.compile(it, pp);
+                    i.compile(it, pp)?;
                 }
                 pp.push(ConstructVector(n));
             }
             E::Set(mut ve) => {
                 let n = ve.len();
                 for i in ve.drain(..) {
-                    i.compile(it, pp);
+                    i.compile(it, pp)?;
                 }
                 pp.push(ConstructSet(n));
             }
+            E::VecPop(n) => {
+                let loc = pp.local(&n);
+                pp.push(VecPop(loc));
+            }
 
             E::Binary(box _lhs, BinOp::And, box _rhs) => {
                 panic!("can't compile And yet");
@@ -58,8 +158,8 @@ impl Expression {
             }
 
             E::Binary(box lhs, op, box rhs) => {
-                lhs.compile(it, pp);
-                rhs.compile(it, pp);
+                lhs.compile(it, pp)?;
+                rhs.compile(it, pp)?;
                 pp.push(match op {
                     BinOp::And => unreachable!(),
                     BinOp::Or => unreachable!(),
@@ -78,5 +178,32 @@ impl Expression {
                 });
             }
         }
+        Ok(())
+    }
+
+    // the value of a literal with no variables or calls in it, if this is one
+    fn ground(&self, it: &mut Interns, pp: &Procedure1) -> Option<Value> {
+        use Expression as E;
+        match self {
+            E::IntLiteral(i) => Some(Value::Integer(*i)),
+            E::Compound(s, ve) if ve.is_empty() => match pp.scope().constant(s) {
+                Ok(Some(ix)) => pp.scope().value(ix), // not in the repl, whose consts are already in the executable
+                Ok(None) => Some(Value::compound(it.intern(s), vec![])),
+                Err(_) => None, // compiling it properly will report it
+            },
+            E::Compound(s, ve) => {
+                let args = ve.iter().map(|e| e.ground(it, pp)).collect::<Option<Vec<_>>>()?;
+                Some(Value::compound(it.intern(s), args))
+            }
+            E::Vector(ve) => {
+                let args = ve.iter().map(|e| e.ground(it, pp)).collect::<Option<Vec<_>>>()?;
+                Some(Value::vector(args))
+            }
+            E::Set(ve) => {
+                let args = ve.iter().map(|e| e.ground(it, pp)).collect::<Option<_>>()?;
+                Some(Value::set(args))
+            }
+            _ => None,
+        }
     }
 }
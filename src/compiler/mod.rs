@@ -1,7 +1,12 @@
 mod block;
+mod captures;
 mod condition;
+mod constants;
+mod declarations;
 mod executable;
 mod expression;
 mod pattern;
 mod procedure;
 mod statement;
+
+pub use executable::{compile_modules, Options};
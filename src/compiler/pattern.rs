@@ -4,12 +4,14 @@ use crate::irs::instruction1;
 use crate::irs::procedure1;
 use crate::primitive::{Functor, Operand};
 
+use crate::errors::compiler::*;
+
 use ast1::Pattern;
 use instruction1::Instruction1;
 use procedure1::Procedure1;
 
 impl Pattern {
-    pub fn compile_destructure(self, it: &mut Interns, pp: &mut Procedure1) {
+    pub fn compile_destructure(self, it: &mut Interns, pp: &mut Procedure1) -> Compiler<()> {
         // there is already a thing on the stack which is the target of the destructure op
         use Instruction1::*;
 
@@ -22,26 +24,33 @@ impl Pattern {
                 pp.push(SetAssert(loc));
             }
             Pattern::Compound(s, mut v) => {
+                if v.is_empty() {
+                    if let Some(ix) = pp.scope().constant(&s)? {
+                        pp.push(EqualsOperandAssert(Operand::Const(ix)));
+                        return Ok(());
+                    }
+                }
                 pp.push(DestructCompound(Functor(it.intern(&s), v.len())));
 
                 for i in v.drain(..) {
-                    i.compile_destructure(it, pp);
+                    i.compile_destructure(it, pp)?;
                 }
             }
             Pattern::WcCompound(mut v) => {
                 pp.push(Destruct(v.len()));
 
                 for i in v.drain(..) {
-                    i.compile_destructure(it, pp);
+                    i.compile_destructure(it, pp)?;
                 }
             }
             Pattern::Vector(mut v) => {
                 pp.push(DestructVector(v.len()));
 
                 for i in v.drain(..) {
-                    i.compile_destructure(it, pp);
+                    i.compile_destructure(it, pp)?;
                 }
             }
         }
+        Ok(())
     }
 }
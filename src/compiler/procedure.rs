@@ -1,26 +1,83 @@
 use crate::interns::Interns;
 use crate::irs::ast1;
+use crate::irs::instruction1;
 use crate::irs::procedure1;
 use crate::irs::procedure2;
 use crate::primitive::Functor;
 
 use crate::errors::compiler::*;
 
-use ast1::{Expression, Pattern, Procedure, Statement};
-use procedure1::Procedure1;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use ast1::{Block, Expression, Pattern, Procedure, Statement};
+use instruction1::Instruction1;
+use procedure1::{OptLevel, Procedure1, Scope};
 use procedure2::Procedure2;
 
-impl Procedure {
-    pub fn compile(self, it: &mut Interns) -> Compiler<Procedure2> {
-        let mut pp = Procedure1::new(Functor(it.intern(&self.name), self.args.len()));
+// `clauses` all share a name and arity, and are tried in order.
+// returns the procedure itself, then any procedures lifted out of its lambdas
+pub fn compile_clauses(
+    it: &mut Interns, scope: &Rc<Scope>, opt: OptLevel, clauses: Vec<Procedure>,
+) -> Compiler<Vec<Procedure2>> {
+    let name = scope.qualify(&clauses[0].name);
+    let public = clauses[0].public;
+    if clauses.iter().any(|c| c.public != public) {
+        return Err(Error::MixedVisibility(name, clauses[0].args.len()));
+    }
+
+    let clauses = clauses.into_iter().map(|c| (c.args, c.body)).collect();
+    compile_procedure(it, scope, opt, &name, public, clauses)
+}
+
+// `name` is taken as already qualified
+pub fn compile_procedure(
+    it: &mut Interns, scope: &Rc<Scope>, opt: OptLevel,
+    name: &str, public: bool, clauses: Vec<(Vec<Pattern>, Block)>,
+) -> Compiler<Vec<Procedure2>> {
+    use Instruction1::*;
 
-        // compile args block
-        // it's assumed the top will always be on the stack
-        let artificial_lhs = Pattern::WcCompound(self.args);
-        let first_statement = Statement::Destructure(artificial_lhs, Expression::NoOp);
-        first_statement.compile(it, &mut pp)?;
-        self.body.compile(it, &mut pp)?;
+    let arity = clauses[0].0.len();
+    let mut pp = Procedure1::new(Functor(it.intern(name), arity), scope.clone());
+    if public { pp.make_public(); }
+    pp.set_opt_level(opt);
 
-        pp.compile()
+    // it's assumed the call will always be on the stack
+    let lb_end = pp.create_label();
+    let mut exhausted = false;
+    for (args, body) in clauses {
+        if irrefutable(&args) {
+            // nothing after this clause can be reached, so there's no need to be careful
+            let artificial_lhs = Pattern::WcCompound(args);
+            let first_statement = Statement::Destructure(artificial_lhs, Expression::NoOp);
+            first_statement.compile(it, &mut pp)?;
+            body.compile(it, &mut pp)?;
+            exhausted = true;
+            break;
+        }
+
+        // destructuring under a mark is atomic, so a clause that doesn't match leaves no locals behind
+        let lb_next = pp.create_label();
+        pp.push(Mark(lb_next, true));
+        Pattern::WcCompound(args).compile_destructure(it, &mut pp)?;
+        pp.push(Unmark);
+        body.compile(it, &mut pp)?;
+        pp.push(Jump(lb_end));
+        pp.anchor_label(lb_next)?;
     }
+    if !exhausted { pp.push(NoClauseMatched); }
+    pp.anchor_label(lb_end)?;
+
+    let mut lifted = pp.take_lifted();
+    let mut compiled = vec![pp.compile()?];
+    compiled.append(&mut lifted);
+    Ok(compiled)
+}
+
+fn irrefutable(args: &[Pattern]) -> bool {
+    let mut seen = HashSet::new();
+    args.iter().all(|arg| match arg {
+        Pattern::Variable(n) => seen.insert(n),
+        _ => false,
+    })
 }
@@ -1,5 +1,6 @@
 use crate::interns::Interns;
 use crate::irs::ast1;
+use crate::irs::executable1::Executable1;
 use crate::irs::instruction1;
 use crate::irs::procedure1;
 use crate::irs::procedure2;
@@ -7,19 +8,26 @@ use crate::primitive::{Functor, Local, Operand};
 
 use crate::errors::compiler::*;
 
-use ast1::{Statement};
+use std::rc::Rc;
+
+use ast1::{Expression, Statement};
 use instruction1::Instruction1;
-use procedure1::Procedure1;
+use procedure1::{Procedure1, Scope};
 use procedure2::Procedure2;
 
 impl Statement {
-    pub fn compile_repl(self, it: &mut Interns) -> Compiler<(
+    // against an executable that's already loaded, whose consts and globals it can use
+    pub fn compile_repl<H>(self, it: &mut Interns, loaded: &Executable1<H>) -> Compiler<(
         std::collections::HashMap<String, Local>,
         Procedure2,
     )> {
-        let mut preprocedure = Procedure1::new(Functor(it.intern("repl"), 0));
+        let scope = Scope::repl(Rc::new(loaded.constant_names.clone()), Rc::new(loaded.global_names.clone()));
+        let mut preprocedure = Procedure1::new(Functor(it.intern("repl"), 0), Rc::new(scope));
+        preprocedure.keep_locals();
 
         self.compile(it, &mut preprocedure)?;
+        // the executable is already loaded, so there's nowhere to put a lifted procedure
+        if !preprocedure.take_lifted().is_empty() { return Err(Error::LambdaOutsideModule); }
         preprocedure.push(Instruction1::Push(Operand::Integer(1)));
         preprocedure.push(Instruction1::Ret);
 
@@ -38,7 +46,7 @@ impl Statement {
                 let lb_else = pp.create_label();
                 let lb_done = pp.create_label();
 
-                cond.compile(it, pp, lb_else);
+                cond.compile(it, pp, lb_else)?;
 
                 pp.anchor_label(lb_then)?;
                 bl_then.compile(it, pp)?;
@@ -57,20 +65,116 @@ impl Statement {
                 pp.anchor_label(lb_done)?;
             }
             Statement::Assign(lhs, rhs) => {
-                rhs.compile(it, pp);
+                rhs.compile(it, pp)?;
                 let ix = pp.local(&lhs);
                 pp.push(Set(ix));
             }
+            Statement::VecPush(lhs, rhs) => {
+                rhs.compile(it, pp)?;
+                let ix = pp.local(&lhs);
+                pp.push(VecPush(ix));
+            }
+            Statement::VecSet(lhs, index, rhs) => {
+                index.compile(it, pp)?;
+                rhs.compile(it, pp)?;
+                let ix = pp.local(&lhs);
+                pp.push(VecSet(ix));
+            }
+            Statement::SetArg(lhs, arg, rhs) => {
+                rhs.compile(it, pp)?;
+                let ix = pp.local(&lhs);
+                pp.push(CompoundSetArg(ix, arg));
+            }
             Statement::Destructure(lhs, rhs) => {
-                rhs.compile(it, pp);
-                lhs.compile_destructure(it, pp); // don't unwind on fail, since we aren't in a conditional situation
+                rhs.compile(it, pp)?;
+                lhs.compile_destructure(it, pp)?; // don't unwind on fail, since we aren't in a conditional situation
+            }
+            Statement::Match(scrutinee, arms) => {
+                let lb_done = pp.create_label();
+                let n_arms = arms.len();
+
+                scrutinee.compile(it, pp)?;
+                for (i, (pat, bl)) in arms.into_iter().enumerate() {
+                    if i + 1 == n_arms {
+                        // nothing left to try: like a let, a mismatch on the last arm is a runtime error
+                        pat.compile_destructure(it, pp)?;
+                        bl.compile(it, pp)?;
+                        break;
+                    }
+
+                    // keep the scrutinee on failure so the next arm can try it
+                    let lb_next = pp.create_label();
+                    pp.push(Mark(lb_next, true));
+                    pat.compile_destructure(it, pp)?;
+                    pp.push(Unmark);
+                    bl.compile(it, pp)?;
+                    pp.push(Jump(lb_done));
+                    pp.anchor_label(lb_next)?;
+                }
+
+                pp.anchor_label(lb_done)?;
+            }
+            Statement::Send(pid, msg) => {
+                pid.compile(it, pp)?;
+                msg.compile(it, pp)?;
+                pp.push(Send);
+            }
+            Statement::Receive(arms) => {
+                // Every arm gets a look at each message in turn. A message none of them
+                // match stays in the mailbox, and the next one is tried.
+                let lb_top = pp.create_label();
+                let lb_done = pp.create_label();
+
+                pp.anchor_label(lb_top)?;
+                pp.push(ReceiveNext);
+                for (pat, bl) in arms.into_iter() {
+                    let lb_next = pp.create_label();
+                    pp.push(Mark(lb_next, true));
+                    pat.compile_destructure(it, pp)?;
+                    pp.push(Unmark);
+                    pp.push(ReceiveAccept);
+                    bl.compile(it, pp)?;
+                    pp.push(Jump(lb_done));
+                    pp.anchor_label(lb_next)?;
+                }
+                pp.push(Pop);
+                pp.push(Jump(lb_top));
+
+                pp.anchor_label(lb_done)?;
+            }
+            Statement::StorePut(key, value) => {
+                key.compile_key(it, pp)?;
+                value.compile(it, pp)?;
+                pp.push(StorePut);
+            }
+            Statement::Atomic(body) => {
+                // an abort comes out at the end, with the savepoint already rolled back
+                let lb_done = pp.create_label();
+                pp.push(Savepoint);
+                pp.enter_atomic(lb_done);
+                body.compile(it, pp)?;
+                pp.leave_atomic();
+                pp.push(Release);
+                pp.anchor_label(lb_done)?;
+            }
+            Statement::Abort => {
+                let lb_done = match pp.atomic() {
+                    Some(lb_done) => lb_done,
+                    None => { return Err(Error::AbortOutsideAtomic); }
+                };
+                pp.push(Rollback);
+                pp.push(Jump(lb_done));
             }
             Statement::Eval(expression) => {
-                expression.compile(it, pp);
+                expression.compile(it, pp)?;
                 pp.push(Pop);
             }
+            Statement::Ret(Expression::Call(box e)) if !pp.keeps_locals() => {
+                e.compile_callee(it, pp)?;
+                pp.push(TailCall);
+            }
             Statement::Ret(expression) => {
-                expression.compile(it, pp);
+                expression.compile(it, pp)?;
                 pp.push(Ret);
             }
         };
@@ -1,3 +1,6 @@
+use crate::irs::ast1::Span;
+use crate::typer::Type;
+
 pub type Compiler<T> = Result<T, Error>;
 
 #[derive(Debug)]
@@ -6,4 +9,25 @@ pub enum Error {
     NotAnchored(usize),
 
     DuplicatedArg(String),
+    DuplicatedProcedure(String, usize), // qualified name, arity
+    MixedVisibility(String, usize), // some clauses are `pub fn` and some aren't
+    LambdaOutsideModule,
+    NotImported(String, String), // module, the qualified name used from outside it
+    AbortOutsideAtomic, // including in a lambda inside one: the lambda is its own procedure
+
+    DuplicatedConstant(String), // qualified name
+    NotConstant(String), // a const whose value uses a variable, a call or a lambda
+    BadConstant(String), // a const whose arithmetic fails (wrong types, overflow, divide by zero)
+    RecursiveConstant(String),
+    ConstantClash(String), // a const named like a 0-ary procedure or a constructor, which then couldn't be written
+
+    DuplicatedType(String),
+    DuplicatedConstructor(String),
+    UnknownType(String),
+    UndeclaredFunctor(String, usize), // a compound with args whose functor isn't a constructor or procedure
+    WrongArity(String, usize), // a declared constructor used with the wrong number of args
+    NonExhaustiveMatch(String, Vec<String>), // type, constructors with no arm
+
+    TypeMismatch(Span, Type, Type), // expected, found
+    InfiniteType(Span),
 }
\ No newline at end of file
@@ -0,0 +1,27 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type Loading<T> = Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String), // rendered, since the parser's errors borrow the source
+    NotFound(String), // a module that isn't on the search path
+    WrongModuleName(PathBuf, String, Option<String>), // expected, declared
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Parse(path, message) => write!(f, "{}: doesn't parse:\n{}", path.display(), message),
+            Error::NotFound(name) => write!(f, "no module {} on the search path", name),
+            Error::WrongModuleName(path, expected, declared) => match declared {
+                Some(declared) => write!(f, "{}: should be module {}, but says it's {}", path.display(), expected, declared),
+                None => write!(f, "{}: should be module {}, but doesn't say", path.display(), expected),
+            },
+        }
+    }
+}
@@ -1,3 +1,4 @@
 pub mod compiler;
+pub mod loader;
 pub mod parser;
 pub mod runtime;
\ No newline at end of file
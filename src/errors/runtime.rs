@@ -1,8 +1,12 @@
+use crate::interns::Intern;
+use crate::primitive::{Functor, Pid, Value};
+
+use std::fmt;
+
 pub type Runtime<T> = Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
-    UpdatedWhileUpdating, // happens if an update runs into ownership issues
     OutOfCode, // happens if we run to the end of the procedure (because I don't handle that yet)
     NoMoreFrames, // happens if we run out of stack frames, probably because a return is missed
     NoMoreValues, // happens if the stack runs out of expressions. (code-gen error)
@@ -14,9 +18,26 @@ pub enum Error {
     ConditionalWrongType, // for conditional, wrong type (not a bool)
     DestructWrongType, // when attempting to Destruct the wrong type
     NotNumbers, // for numeric operation, both tops must be numbers
+    InPlaceWrongType, // for VecPush and friends on a local that holds the wrong kind of value
+    IndexOutOfRange, // for VecSet and CompoundSetArg past the end
+    NotAnIndex, // for VecSet with an index that isn't an integer
+    PopEmpty, // for VecPop on an empty vector
 
     NoSuchProcedure, // for calls to a nonexistent procedure
+    NotPublic, // for calls from outside (the host or the repl) to a procedure that isn't `pub fn`
+    NoClauseMatched(Value), // for calls whose args don't match any clause of the procedure
     CallNotCompound, // a procedure name is a functor, so only compounds are callable
+    Native { functor: Functor<Intern>, message: String, call: Value }, // for a native procedure that failed
+
+    NoScheduler, // for spawn, self, send and receive in a VM that isn't running in a Scheduler
+    SendNotPid, // for sending to something that isn't a pid
+    Deadlocked, // for joining a process that's waiting on messages nothing is left to send
+    Yielded, // for joining a process that's waiting on the host to resume it
+    OtherYielded(Pid), // for joining a process that's waiting on messages while that one waits on the host
+    NoStore, // for get and put in a VM that wasn't given a Store
+    NotInStore(Value), // for a get of a key that nothing was put under (and isn't a global)
+    NotAtomic, // for a Release or Rollback outside an atomic block (the compiler doesn't emit those)
+    ExtendNotCompound, // only compounds can be given more args
 
     CantMarkTwice, // for code that tries to mark more than once at the same time on the same frame
     UnmarkMustBeMarked, // for code that unmarks while not marked
@@ -25,3 +46,21 @@ pub enum Error {
     UnwindStackTooSmall, // for code that unwinds when there are less stack elements than it started with
     MarkInvalidInstruction, // for marked code that tries to use an instruction that is not allowed
 }
+
+// What a native procedure fails with. The VM adds which procedure it was, and the call
+#[derive(Debug)]
+pub struct NativeError {
+    pub message: String,
+}
+
+impl NativeError {
+    pub fn new(message: impl Into<String>) -> Self {
+        NativeError { message: message.into() }
+    }
+}
+
+impl fmt::Display for NativeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
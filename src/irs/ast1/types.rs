@@ -1,23 +1,73 @@
 #[derive(Debug)]
 pub struct Module {
+    pub name: Option<String>, // None for the root module, whose names aren't qualified
+    pub imports: Vec<String>,
     pub procedures: Vec<Procedure>,
+    pub types: Vec<TypeDecl>,
+    pub consts: Vec<ConstDecl>,
+    pub globals: Vec<GlobalDecl>,
+}
+
+// `const name = value.` -- the value is evaluated at compile time, so it can't use variables or calls
+#[derive(Debug)]
+pub struct ConstDecl {
+    pub name: String,
+    pub value: Expression,
+}
+
+// `global name = value.` -- a key in the host's Store, which `get name` finds `value` under
+// until the program puts something else there. The value is evaluated like a const's
+#[derive(Debug)]
+pub struct GlobalDecl {
+    pub name: String,
+    pub value: Expression,
+}
+
+#[derive(Debug)]
+pub struct TypeDecl {
+    pub name: String,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    pub name: String,
+    pub args: Vec<TypeExpr>,
+}
+
+#[derive(Debug)]
+pub enum TypeExpr {
+    Named(String), // int, bool, any, or a declared type
+    Vector(Box<TypeExpr>),
+    Set(Box<TypeExpr>),
 }
 
 #[derive(Debug)]
 pub struct Procedure {
+    pub span: Span,
+    pub public: bool, // `pub fn`: the host and the repl can call it
     pub name: String,
     pub args: Vec<Pattern>,
     pub body: Block,
 }
 
 #[derive(Debug)]
-pub struct Block(pub Vec<Statement>);
+pub struct Block(pub Vec<(Span, Statement)>);
 
 #[derive(Debug)]
 pub enum Statement {
     If(Condition, Block, Option<Block>),
     Assign(String, Expression),
+    VecPush(String, Expression), // push @v, x.
+    VecSet(String, Expression, Expression), // now @v[i] = x.
+    SetArg(String, usize, Expression), // now @c.0 = x.
     Destructure(Pattern, Expression),
+    Match(Expression, Vec<(Pattern, Block)>),
+    Send(Expression, Expression), // send @pid msg.
+    Receive(Vec<(Pattern, Block)>), // waits for the first message in the mailbox that some arm matches
+    StorePut(Expression, Expression), // put key = value.
+    Atomic(Block), // atomic { .. }: an abort inside puts the store back how it was at the start
+    Abort, // abort.: rolls back the innermost atomic block, and carries on after it
     Eval(Expression),
     Ret(Expression),
 }
@@ -44,9 +94,16 @@ pub enum Expression {
     IntLiteral(i64),
     Variable(String),
     Call(Box<Expression>),
+    Apply(Box<Expression>, Vec<Expression>), // add args to an existing compound
+    Lambda(Vec<Pattern>, Block),
     Compound(String, Vec<Expression>),
     Vector(Vec<Expression>),
     Set(Vec<Expression>),
+    VecPop(String), // pop @v: takes the last element out of the local
+    Spawn(Box<Expression>), // spawn call f(args): the call runs in a new process, and this is its pid
+    SelfPid, // self: this process's pid
+    Yield(Box<Expression>), // yield x: hands x to the host, and is whatever the host resumes with
+    StoreGet(Box<Expression>), // get key: what the host's Store has under key (see GlobalDecl)
 
     Binary(Box<Expression>, BinOp, Box<Expression>)
 }
@@ -62,3 +119,19 @@ pub enum BinOp {
     Lt, Gt,
     Eq, Ne,
 }
+
+// Nom only hands us the remaining input, so positions are stored as the number of bytes
+// left in the source (start, end). Use `locate` with the source to get something readable.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Span(pub usize, pub usize);
+
+impl Span {
+    // 1-based line and column of the start of the span
+    pub fn locate(&self, source: &str) -> (usize, usize) {
+        let offset = source.len().saturating_sub(self.0);
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+        (line, column)
+    }
+}
@@ -1,22 +1,40 @@
 use std::collections::HashMap;
 
+use crate::errors::runtime::NativeError;
 use crate::interns::{Intern, Interns};
 use crate::irs::procedure2::Procedure2;
 use crate::primitive::{Functor, Value};
+use crate::typer::Types;
 
 use std::fmt;
 
+// `H` is whatever the host hands every native as `&mut H` while the VM runs: game state, say
 #[derive(Debug)]
-pub struct Executable1 {
-    pub procedures: HashMap<Functor<Intern>, FFIProcedure>,
+pub struct Executable1<H = ()> {
+    pub procedures: HashMap<Functor<Intern>, FFIProcedure<H>>,
+    pub constants: Vec<Value>, // for Operand::Const
+    // qualified name -> index into `constants`, for compiling against this later (the repl does)
+    pub constant_names: HashMap<String, usize>,
+    pub global_names: HashMap<String, usize>,
+    // every procedure's inferred type, by qualified name, if compiled with `check_types`
+    pub types: Option<Types>,
 }
 
-pub enum FFIProcedure {
-    Native(Box<Fn(&Interns, &Executable1, Value) -> Value>),
+pub enum FFIProcedure<H = ()> {
+    Native(Box<dyn Fn(&Interns, &Executable1<H>, &mut H, Value) -> Result<NativeStep<H>, NativeError>>),
     Dynamic(Procedure2),
 }
 
-impl fmt::Debug for FFIProcedure {
+// What a native comes to. A native can't run pteropus code itself: it asks the VM to make a
+// call and gets the result in `then`, so the call spends fuel and can stop like any other
+pub enum NativeStep<H = ()> {
+    Done(Value),
+    Call(Value, Continuation<H>), // the call, as for `call`, and what to do with its result
+}
+
+pub type Continuation<H = ()> = Box<dyn FnOnce(&Interns, &Executable1<H>, &mut H, Value) -> Result<NativeStep<H>, NativeError>>;
+
+impl<H> fmt::Debug for FFIProcedure<H> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             FFIProcedure::Native(_) => { write!(f, "Native(:native code:)") }
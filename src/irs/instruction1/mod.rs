@@ -7,15 +7,28 @@ pub enum Instruction1 {
 
     Set(Local), SetAssert(Local),
     Get(Local),
+    // in place on a local: the value is only copied first if something else shares it
+    VecPush(Local), VecPop(Local), VecSet(Local), CompoundSetArg(Local, usize),
 
     Assert,
     Jump(Label), JumpNo(Label),
 
-    Pop, Ret, Call,
+    Pop, Ret, Call, TailCall,
+    Yield, // stops the VM with the value on top of the stack: resuming pushes the host's answer
+    NoClauseMatched, // fails with the call, which is on top of the stack
+
+    // only in a Scheduler. ReceiveNext waits in place until there's a message it hasn't looked at
+    Spawn, SelfPid, Send, ReceiveNext, ReceiveAccept,
+
+    // only with a Store. StoreGetOr has a value to push if there's nothing under the key
+    StoreGet, StoreGetOr, StorePut,
+    // atomic blocks: Rollback undoes the puts since the innermost Savepoint, Release keeps them
+    Savepoint, Release, Rollback,
 
     Mark(Label, bool), Unmark,
     DestructCompound(Functor<Intern>), DestructVector(usize), Destruct(usize),
     ConstructCompound(Functor<Intern>), ConstructVector(usize), ConstructSet(usize),
+    ExtendCompound(usize),
 
     Equals, EqualsOperandAssert(Operand),
 
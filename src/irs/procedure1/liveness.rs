@@ -0,0 +1,60 @@
+use crate::irs::instruction2::{Instruction2, Ip};
+
+use Instruction2::*;
+
+// Turns the last use of each local into a Take, which moves the value out of the frame
+// instead of cloning it. A local is live at an instruction if some path from there
+// reads it (with Get, SetAssert or an in-place op) before anything overwrites it (with Set).
+// `keep_locals` means the host reads every local after Ret, so they're all live there.
+pub fn take_last_uses(instructions: &mut [Instruction2], vars: usize, keep_locals: bool) {
+    let n = instructions.len();
+    let mut live_in = vec![vec![false; vars]; n];
+
+    // most jumps go forward, so sweeping backwards settles nearly everything on the first pass.
+    // a receive loops back to its top, though, so keep going until a pass changes nothing
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..n).rev() {
+            let mut live = live_out(instructions, &live_in, i, vars, keep_locals);
+            match instructions[i] {
+                Set(l) => { live[l.0] = false; }
+                // the in-place ops read the local as well as writing it
+                Get(l) | Take(l) | SetAssert(l) |
+                VecPush(l) | VecPop(l) | VecSet(l) | CompoundSetArg(l, _) => { live[l.0] = true; }
+                _ => {}
+            }
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+
+    for i in 0..n {
+        if let Get(l) = instructions[i] {
+            if !live_out(instructions, &live_in, i, vars, keep_locals)[l.0] {
+                instructions[i] = Take(l);
+            }
+        }
+    }
+}
+
+fn live_out(
+    instructions: &[Instruction2], live_in: &[Vec<bool>], i: usize, vars: usize, keep_locals: bool,
+) -> Vec<bool> {
+    let mut live = vec![false; vars];
+    let mut from = |ip: usize| {
+        if ip < live_in.len() {
+            for (l, is_live) in live_in[ip].iter().enumerate() { live[l] |= *is_live; }
+        }
+    };
+    match instructions[i] {
+        Jump(Ip(to)) => from(to),
+        JumpNo(Ip(to)) | Mark(Ip(to), _) => { from(i + 1); from(to); }
+        Ret if keep_locals => { return vec![true; vars]; }
+        Ret | TailCall | NoClauseMatched => {}
+        _ => from(i + 1),
+    }
+    live
+}
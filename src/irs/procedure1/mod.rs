@@ -1,6 +1,13 @@
+mod liveness;
+mod optimize;
+mod scope;
+
+pub use optimize::OptLevel;
+pub use scope::{qualify, Pool, Scope};
+
 use crate::errors::compiler::{Compiler, Error};
-use crate::interns::Intern;
-use crate::primitive::{Functor, Local};
+use crate::interns::{Intern, Interns};
+use crate::primitive::{Functor, Local, Operand, Value};
 
 use super::instruction1;
 use super::instruction2;
@@ -10,27 +17,51 @@ use instruction1::{Instruction1, Label};
 use instruction2::{Instruction2, Ip};
 
 use std::collections::HashMap;
+use std::rc::Rc;
 
 pub struct Procedure1 {
     functor: Functor<Intern>,
+    scope: Rc<Scope>,
 
     instructions: Vec<Instruction1>,
     anchor_labels: HashMap<Label, Ip>,
     next_label: Label,
     local_name_to_ix: HashMap<String, Local>,
     next_local: Local,
+
+    // procedures for the lambdas in this one, already compiled
+    lifted: Vec<Procedure2>,
+    next_lambda: usize,
+
+    // the end of each `atomic` block being compiled, innermost last: where an `abort` goes
+    atomics: Vec<Label>,
+
+    // the host wants the locals once we return (the repl does), so frames can't be discarded early
+    keep_locals: bool,
+    public: bool,
+    opt: OptLevel,
 }
 
 impl Procedure1 {
-    pub fn new(functor: Functor<Intern>) -> Self {
+    pub fn new(functor: Functor<Intern>, scope: Rc<Scope>) -> Self {
         Procedure1 {
             functor,
+            scope,
 
             instructions: vec![],
             anchor_labels: HashMap::new(),
             next_label: Label(0),
             local_name_to_ix: HashMap::new(),
             next_local: Local(0),
+
+            lifted: vec![],
+            next_lambda: 0,
+
+            atomics: vec![],
+
+            keep_locals: false,
+            public: false,
+            opt: OptLevel::O0,
         }
     }
 
@@ -38,6 +69,30 @@ impl Procedure1 {
         &self.local_name_to_ix
     }
 
+    pub fn scope(&self) -> &Rc<Scope> {
+        &self.scope
+    }
+
+    pub fn keep_locals(&mut self) {
+        self.keep_locals = true;
+    }
+
+    pub fn keeps_locals(&self) -> bool {
+        self.keep_locals
+    }
+
+    pub fn make_public(&mut self) {
+        self.public = true;
+    }
+
+    pub fn set_opt_level(&mut self, opt: OptLevel) {
+        self.opt = opt;
+    }
+
+    pub fn opt_level(&self) -> OptLevel {
+        self.opt
+    }
+
     pub fn local(&mut self, s: &str) -> Local {
         match self.local_name_to_ix.get(s) {
             Some(i) => { return *i; }
@@ -49,6 +104,44 @@ impl Procedure1 {
         return nx;
     }
 
+    // an operand for a value known at compile time: None if it would need the constant pool
+    // and there isn't one to add to
+    pub fn operand(&self, value: Value) -> Option<Operand> {
+        match value {
+            Value::Integer(i) => Some(Operand::Integer(i)),
+            Value::Bool(b) => Some(Operand::Bool(b)),
+            value => self.scope.intern(value).map(Operand::Const),
+        }
+    }
+
+    pub fn lambda_name(&mut self, it: &Interns) -> String {
+        // # and / aren't identifier characters, so these can't collide with anything in source.
+        // the arity keeps f/1's lambdas apart from f/2's
+        let name = format!("{}/{}#{}", it.to_string(self.functor.0).unwrap_or("?"), self.functor.1, self.next_lambda);
+        self.next_lambda += 1;
+        name
+    }
+
+    pub fn lift(&mut self, mut procedures: Vec<Procedure2>) {
+        self.lifted.append(&mut procedures);
+    }
+
+    pub fn take_lifted(&mut self) -> Vec<Procedure2> {
+        std::mem::replace(&mut self.lifted, vec![])
+    }
+
+    pub fn enter_atomic(&mut self, end: Label) {
+        self.atomics.push(end);
+    }
+
+    pub fn leave_atomic(&mut self) {
+        self.atomics.pop();
+    }
+
+    pub fn atomic(&self) -> Option<Label> {
+        self.atomics.last().cloned()
+    }
+
     pub fn push(&mut self, i: Instruction1) {
         self.instructions.push(i);
     }
@@ -72,6 +165,8 @@ impl Procedure1 {
     }
 
     pub fn compile(mut self) -> Compiler<Procedure2> {
+        if self.opt >= OptLevel::O1 { self.optimize(); }
+
         let mut is2 = vec![];
         for inst in self.instructions.drain(..) {
             use Instruction1 as A;
@@ -84,6 +179,22 @@ impl Procedure1 {
                 A::Set(loc) => B::Set(loc),
                 A::SetAssert(loc) => B::SetAssert(loc),
                 A::Get(loc) => B::Get(loc),
+                A::Yield => B::Yield,
+                A::Spawn => B::Spawn,
+                A::SelfPid => B::SelfPid,
+                A::Send => B::Send,
+                A::ReceiveNext => B::ReceiveNext,
+                A::ReceiveAccept => B::ReceiveAccept,
+                A::StoreGet => B::StoreGet,
+                A::StoreGetOr => B::StoreGetOr,
+                A::StorePut => B::StorePut,
+                A::Savepoint => B::Savepoint,
+                A::Release => B::Release,
+                A::Rollback => B::Rollback,
+                A::VecPush(loc) => B::VecPush(loc),
+                A::VecPop(loc) => B::VecPop(loc),
+                A::VecSet(loc) => B::VecSet(loc),
+                A::CompoundSetArg(loc, arg) => B::CompoundSetArg(loc, arg),
                 A::Jump(l) => B::Jump(
                     if let Some(anc) = self.anchor_labels.get(&l) { *anc }
                     else { return Err(Error::NotAnchored(l.0)) }
@@ -95,6 +206,8 @@ impl Procedure1 {
                 A::Pop => B::Pop,
                 A::Ret => B::Ret,
                 A::Call => B::Call,
+                A::TailCall => B::TailCall,
+                A::NoClauseMatched => B::NoClauseMatched,
 
                 A::Mark(l, keep_on_failure) => B::Mark(
                     if let Some(anc) = self.anchor_labels.get(&l) { *anc }
@@ -106,6 +219,7 @@ impl Procedure1 {
                 A::DestructVector(s) => B::DestructVector(s),
                 A::Destruct(s) => B::Destruct(s),
                 A::ConstructCompound(Functor(f, a)) => B::ConstructCompound(Functor(f, a)),
+                A::ExtendCompound(n) => B::ExtendCompound(n),
                 A::ConstructVector(s) => B::ConstructVector(s),
                 A::ConstructSet(s) => B::ConstructSet(s),
 
@@ -117,10 +231,14 @@ impl Procedure1 {
                 A::Eq => B::Eq, A::Ne => B::Ne,
             })
         };
+        if self.opt >= OptLevel::O1 {
+            liveness::take_last_uses(&mut is2, self.next_local.0, self.keep_locals);
+        }
         Ok(Procedure2 {
             functor: Functor(self.functor.0, self.functor.1),
             vars: self.next_local.0,
             instructions: is2,
+            public: self.public,
         })
     }
 }
@@ -0,0 +1,310 @@
+use crate::irs::instruction1::{Instruction1, Label};
+use crate::irs::instruction2::Ip;
+use crate::primitive::{Operand, Value};
+
+use std::collections::{HashMap, HashSet};
+
+use super::{Procedure1, Scope};
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum OptLevel {
+    O0, // exactly what the compiler emitted
+    O1, // peephole passes only: nothing that needs to know what's in a local
+}
+
+impl Default for OptLevel {
+    fn default() -> OptLevel { OptLevel::O1 }
+}
+
+// The passes work on the instructions with anchors inline, so deleting an instruction
+// doesn't mean renumbering every label after it.
+#[derive(Clone, Copy, Debug)]
+enum Item {
+    Anchor(Label),
+    Op(Instruction1),
+}
+
+use Item::{Anchor, Op};
+use Instruction1::*;
+
+impl Procedure1 {
+    pub(super) fn optimize(&mut self) {
+        let mut items = self.items();
+        loop {
+            let mut changed = false;
+            changed |= fold_constants(&mut items);
+            changed |= evaluate_marks(&mut items, &self.scope);
+            changed |= cancel_push_pop(&mut items);
+            changed |= thread_jumps(&mut items);
+            changed |= remove_jumps_to_next(&mut items);
+            changed |= remove_dead_code(&mut items);
+            if !changed { break; }
+        }
+        self.set_items(items);
+    }
+
+    fn items(&mut self) -> Vec<Item> {
+        let mut anchors: Vec<(Ip, Label)> = self.anchor_labels.iter().map(|(l, ip)| (*ip, *l)).collect();
+        anchors.sort_by_key(|(ip, l)| (ip.0, l.0));
+
+        let mut items = vec![];
+        let mut anchors = anchors.into_iter().peekable();
+        for (ip, inst) in self.instructions.drain(..).enumerate() {
+            while let Some((Ip(at), l)) = anchors.peek() {
+                if *at != ip { break; }
+                items.push(Anchor(*l));
+                anchors.next();
+            }
+            items.push(Op(inst));
+        }
+        // anything left is anchored past the last instruction
+        for (_, l) in anchors { items.push(Anchor(l)); }
+        items
+    }
+
+    fn set_items(&mut self, items: Vec<Item>) {
+        self.instructions.clear();
+        self.anchor_labels.clear();
+        for item in items {
+            match item {
+                Anchor(l) => { self.anchor_labels.insert(l, Ip(self.instructions.len())); }
+                Op(inst) => self.instructions.push(inst),
+            }
+        }
+    }
+}
+
+fn target(inst: &Instruction1) -> Option<Label> {
+    match inst {
+        Jump(l) | JumpNo(l) | Mark(l, _) => Some(*l),
+        _ => None,
+    }
+}
+
+fn retarget(inst: Instruction1, to: Label) -> Instruction1 {
+    match inst {
+        Jump(_) => Jump(to),
+        JumpNo(_) => JumpNo(to),
+        Mark(_, keep_on_failure) => Mark(to, keep_on_failure),
+        i => i,
+    }
+}
+
+// `2 * 3` to `6`, and conditions on a literal bool to a jump or nothing.
+// Overflow and dividing by zero are left for the VM to complain about.
+fn fold_constants(items: &mut Vec<Item>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < items.len() {
+        let replacement = match (items.get(i), items.get(i + 1), items.get(i + 2)) {
+            (Some(Op(Push(Operand::Integer(a)))), Some(Op(Push(Operand::Integer(b)))), Some(Op(op))) => {
+                fold_binary(*op, *a, *b).map(|v| (3, vec![Op(Push(v))]))
+            }
+            (Some(Op(Push(Operand::Bool(b)))), Some(Op(JumpNo(l))), _) => {
+                Some((2, if *b { vec![] } else { vec![Op(Jump(*l))] }))
+            }
+            (Some(Op(Push(Operand::Bool(true)))), Some(Op(Assert)), _) => Some((2, vec![])),
+            _ => None,
+        };
+        match replacement {
+            Some((n, with)) => {
+                items.splice(i..i + n, with);
+                changed = true;
+                // the result might fold with what came before it
+                i = i.saturating_sub(1);
+            }
+            None => { i += 1; }
+        }
+    }
+    changed
+}
+
+fn fold_binary(op: Instruction1, a: i64, b: i64) -> Option<Operand> {
+    Some(match op {
+        Mul => Operand::Integer(a.checked_mul(b)?),
+        Div => Operand::Integer(a.checked_div(b)?),
+        Add => Operand::Integer(a.checked_add(b)?),
+        Subtract => Operand::Integer(a.checked_sub(b)?),
+
+        Le => Operand::Bool(a <= b),
+        Ge => Operand::Bool(a >= b),
+        Lt => Operand::Bool(a < b),
+        Gt => Operand::Bool(a > b),
+        Eq => Operand::Bool(a == b),
+        Ne => Operand::Bool(a != b),
+        _ => { return None; }
+    })
+}
+
+// A literal pushed straight into a mark: `if let x = x` either always or never matches.
+// Patterns that bind variables are left alone, since whether they match depends on what's already bound.
+fn evaluate_marks(items: &mut Vec<Item>, scope: &Scope) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < items.len() {
+        let (push, value) = match items[i] {
+            Op(Push(Operand::Integer(n))) => (items[i], Value::Integer(n)),
+            Op(Push(Operand::Bool(b))) => (items[i], Value::Bool(b)),
+            Op(Push(Operand::Const(ix))) => match scope.value(ix) {
+                Some(value) => (items[i], value),
+                None => { i += 1; continue; }
+            },
+            _ => { i += 1; continue; }
+        };
+        let (lb_else, keep_on_failure) = match items[i + 1] {
+            Op(Mark(l, keep_on_failure)) => (l, keep_on_failure),
+            _ => { i += 1; continue; }
+        };
+
+        let mut block = vec![];
+        let mut end = None;
+        for (j, item) in items.iter().enumerate().skip(i + 2) {
+            match item {
+                Op(Unmark) => { end = Some(j); break; }
+                Op(inst) => block.push(*inst),
+                Anchor(_) => break, // something jumps in, so this isn't straight-line code
+            }
+        }
+        let end = match end {
+            Some(end) => end,
+            None => { i += 1; continue; }
+        };
+
+        let with = match matches_statically(&value, &block) {
+            Some(true) => vec![],
+            Some(false) if keep_on_failure => vec![push, Op(Jump(lb_else))],
+            Some(false) => vec![Op(Jump(lb_else))],
+            None => { i += 1; continue; }
+        };
+        items.splice(i..end + 1, with);
+        changed = true;
+        i += 1;
+    }
+    changed
+}
+
+// Some(true) if it always matches, Some(false) if it never does, None if that depends on the locals
+fn matches_statically(value: &Value, block: &[Instruction1]) -> Option<bool> {
+    let mut stack = vec![value];
+    let mut binds = false;
+    for inst in block {
+        let top = stack.pop()?;
+        match inst {
+            SetAssert(_) => { binds = true; }
+            Pop => {}
+            DestructCompound(f) => match top {
+                Value::Compound(intern, args) if *intern == f.0 && args.len() == f.1 => {
+                    stack.extend(args.iter().rev());
+                }
+                _ => { return Some(false); }
+            },
+            DestructVector(sz) => match top {
+                Value::Vector(args) if args.len() == *sz => { stack.extend(args.iter().rev()); }
+                _ => { return Some(false); }
+            },
+            Destruct(sz) => match top {
+                Value::Compound(_, args) | Value::Vector(args) if args.len() == *sz => {
+                    stack.extend(args.iter().rev());
+                }
+                _ => { return Some(false); }
+            },
+            EqualsOperandAssert(Operand::Integer(n)) => {
+                if top != &Value::Integer(*n) { return Some(false); }
+            }
+            EqualsOperandAssert(Operand::Bool(b)) => {
+                if top != &Value::Bool(*b) { return Some(false); }
+            }
+            _ => { return None; }
+        }
+    }
+    if binds { None } else { Some(true) }
+}
+
+// `eval 1.` and friends
+fn cancel_push_pop(items: &mut Vec<Item>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < items.len() {
+        match (items[i], items[i + 1]) {
+            (Op(Push(_)), Op(Pop)) => {
+                items.drain(i..i + 2);
+                changed = true;
+                i = i.saturating_sub(1);
+            }
+            _ => { i += 1; }
+        }
+    }
+    changed
+}
+
+// A jump to a jump goes straight to the second one's target.
+fn thread_jumps(items: &mut Vec<Item>) -> bool {
+    let mut next_op: HashMap<Label, Instruction1> = HashMap::new();
+    let mut waiting = vec![];
+    for item in items.iter() {
+        match item {
+            Anchor(l) => waiting.push(*l),
+            Op(inst) => { for l in waiting.drain(..) { next_op.insert(l, *inst); } }
+        }
+    }
+
+    let mut changed = false;
+    for item in items.iter_mut() {
+        if let Op(inst) = item {
+            let mut to = match target(inst) {
+                Some(l) => l,
+                None => continue,
+            };
+            // a loop of jumps would go around forever, so give up after that many hops
+            let mut seen = HashSet::new();
+            while let Some(Jump(next)) = next_op.get(&to) {
+                if !seen.insert(to) { break; }
+                to = *next;
+            }
+            if Some(to) != target(inst) {
+                *inst = retarget(*inst, to);
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+fn remove_jumps_to_next(items: &mut Vec<Item>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < items.len() {
+        if let Op(Jump(l)) = items[i] {
+            let lands_next = items[i + 1..].iter()
+                .take_while(|item| match item { Anchor(_) => true, Op(_) => false })
+                .any(|item| match item { Anchor(a) => *a == l, Op(_) => false });
+            if lands_next {
+                items.remove(i);
+                changed = true;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+// Nothing runs between an unconditional transfer and the next anchor that something jumps to.
+fn remove_dead_code(items: &mut Vec<Item>) -> bool {
+    let referenced: HashSet<Label> = items.iter()
+        .filter_map(|item| match item { Op(inst) => target(inst), Anchor(_) => None })
+        .collect();
+
+    let before = items.len();
+    let mut live = true;
+    items.retain(|item| match item {
+        Anchor(l) if referenced.contains(l) => { live = true; true }
+        Anchor(_) => false,
+        Op(inst) => {
+            let keep = live;
+            if let Ret | TailCall | Jump(_) | NoClauseMatched = inst { live = false; }
+            keep
+        }
+    });
+    items.len() != before
+}
@@ -0,0 +1,143 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::primitive::Value;
+
+use crate::errors::compiler::*;
+
+// What a procedure can see of the module it's defined in.
+#[derive(Debug)]
+pub struct Scope {
+    module: Option<String>,
+    imports: Option<HashSet<String>>, // None for the repl, which can see every loaded module
+    procedures: HashSet<(String, usize)>, // unqualified
+    constants: Rc<HashMap<String, usize>>, // every module's, qualified -> index into the constant pool
+    globals: Rc<HashMap<String, usize>>, // the same, for globals' starting values
+    // the executable's constant pool, which literals are added to as they're compiled.
+    // None for the repl: the executable it runs against is already built
+    pool: Option<Rc<RefCell<Pool>>>,
+}
+
+// The constant pool, with where each value first is, so interning a literal doesn't
+// compare it against everything already there
+#[derive(Debug, Default)]
+pub struct Pool {
+    values: Vec<Value>,
+    index: BTreeMap<Value, usize>,
+}
+
+impl Pool {
+    pub fn new(values: Vec<Value>) -> Pool {
+        let mut index = BTreeMap::new();
+        for (ix, value) in values.iter().enumerate() {
+            index.entry(value.clone()).or_insert(ix);
+        }
+        Pool { values, index }
+    }
+
+    pub fn intern(&mut self, value: Value) -> usize {
+        if let Some(ix) = self.index.get(&value) { return *ix; }
+        self.values.push(value.clone());
+        self.index.insert(value, self.values.len() - 1);
+        self.values.len() - 1
+    }
+
+    pub fn get(&self, ix: usize) -> Option<&Value> {
+        self.values.get(ix)
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+}
+
+impl Scope {
+    // the repl's: the root module's, with every loaded module in sight
+    pub fn repl(constants: Rc<HashMap<String, usize>>, globals: Rc<HashMap<String, usize>>) -> Scope {
+        Scope { module: None, imports: None, procedures: HashSet::new(), constants, globals, pool: None }
+    }
+
+    pub fn new(
+        module: Option<String>, imports: HashSet<String>, procedures: HashSet<(String, usize)>,
+        constants: Rc<HashMap<String, usize>>, globals: Rc<HashMap<String, usize>>,
+        pool: Rc<RefCell<Pool>>,
+    ) -> Scope {
+        Scope { module, imports: Some(imports), procedures, constants, globals, pool: Some(pool) }
+    }
+
+    // where `value` is in the pool, adding it if it isn't yet
+    pub fn intern(&self, value: Value) -> Option<usize> {
+        Some(self.pool.as_ref()?.borrow_mut().intern(value))
+    }
+
+    // what's at `ix` in the pool, if it's known while compiling
+    pub fn value(&self, ix: usize) -> Option<Value> {
+        self.pool.as_ref()?.borrow().get(ix).cloned()
+    }
+
+    pub fn qualify(&self, name: &str) -> String {
+        qualify(&self.module, name)
+    }
+
+    // A qualified name has to be from this module or one it imports
+    fn check(&self, name: &str) -> Compiler<()> {
+        let module = match name.rfind("::") {
+            None => return Ok(()),
+            Some(i) => &name[..i],
+        };
+        let visible = self.module.as_ref().map_or(false, |m| m == module)
+            || self.imports.as_ref().map_or(true, |imports| imports.contains(module));
+        if visible { Ok(()) } else { Err(Error::NotImported(module.to_owned(), name.to_owned())) }
+    }
+
+    // Call targets prefer the module's own procedures. Anything else is left as written,
+    // which means it's either qualified or in the root module (or the library).
+    pub fn resolve(&self, name: &str, arity: usize) -> Compiler<String> {
+        self.check(name)?;
+        if self.procedures.contains(&(name.to_owned(), arity)) { Ok(self.qualify(name)) }
+        else { Ok(name.to_owned()) }
+    }
+
+    // Same rule for consts, which are written like atoms.
+    pub fn constant(&self, name: &str) -> Compiler<Option<usize>> {
+        self.check(name)?;
+        Ok(self.constants.get(&self.qualify(name))
+            .or_else(|| self.constants.get(name))
+            .cloned())
+    }
+
+    // And for globals, which also need the qualified name: it's their key in the store
+    pub fn global(&self, name: &str) -> Compiler<Option<(String, usize)>> {
+        self.check(name)?;
+        let qualified = self.qualify(name);
+        Ok(match self.globals.get(&qualified) {
+            Some(ix) => Some((qualified, *ix)),
+            None => self.globals.get(name).map(|ix| (name.to_owned(), *ix)),
+        })
+    }
+}
+
+pub fn qualify(module: &Option<String>, name: &str) -> String {
+    match module {
+        None => name.to_owned(),
+        Some(m) => format!("{}::{}", m, name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool() {
+        // two consts can have the same value: a literal gets the first
+        let mut pool = Pool::new(vec![Value::Integer(1), Value::Integer(2), Value::Integer(1)]);
+        assert_eq!(pool.intern(Value::Integer(1)), 0);
+        assert_eq!(pool.intern(Value::Integer(2)), 1);
+        assert_eq!(pool.intern(Value::vector(vec![Value::Integer(3)])), 3);
+        assert_eq!(pool.intern(Value::vector(vec![Value::Integer(3)])), 3);
+        assert_eq!(pool.get(3), Some(&Value::vector(vec![Value::Integer(3)])));
+        assert_eq!(pool.into_values().len(), 4);
+    }
+}
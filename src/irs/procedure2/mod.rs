@@ -8,4 +8,5 @@ pub struct Procedure2 {
     pub functor: Functor<Intern>,
     pub instructions: Vec<Instruction2>,
     pub vars: usize,
+    pub public: bool, // private procedures can only be entered from other procedures
 }
@@ -1,89 +1,311 @@
+pub mod typed;
+
+use typed::FromValue;
+
+use crate::errors::runtime::NativeError;
 use crate::interns::{Intern, Interns};
-use crate::irs::executable1::{FFIProcedure, Executable1};
-use crate::primitive::{Functor, Value};
+use crate::irs::executable1::{FFIProcedure, NativeStep};
+use crate::primitive::{unshare, Functor, Value};
 
 use std::collections::HashMap;
 use std::io;
 use std::io::Write;
+use std::mem;
+use std::rc::Rc;
 
-pub trait Library {
+// `H` is the host context the natives get (see Executable1)
+pub trait Library<H = ()> {
     fn add_functions(
         &self,
         interns: &mut Interns,
-        procedures: &mut HashMap<Functor<Intern>, FFIProcedure>,
+        procedures: &mut HashMap<Functor<Intern>, FFIProcedure<H>>,
     );
 }
 
+// both, for a host's own natives on top of the standard ones
+impl<H, A: Library<H>, B: Library<H>> Library<H> for (A, B) {
+    fn add_functions(
+        &self,
+        interns: &mut Interns,
+        procedures: &mut HashMap<Functor<Intern>, FFIProcedure<H>>,
+    ) {
+        self.0.add_functions(interns, procedures);
+        self.1.add_functions(interns, procedures);
+    }
+}
+
 
 pub struct Standard;
 
-impl Library for Standard {
+// doesn't touch the host, so it goes with any
+impl<H: 'static> Library<H> for Standard {
     fn add_functions(
         &self,
         interns: &mut Interns,
-        procedures: &mut HashMap<Functor<Intern>, FFIProcedure>,
+        procedures: &mut HashMap<Functor<Intern>, FFIProcedure<H>>,
     ) {
-        let ok = interns.intern("ok");
+        typed::register(interns, procedures, "print", _print);
         procedures.insert(
-            Functor(interns.intern("print"), 1), FFIProcedure::Native(
-                box move |interns, module, value| _print(ok, interns, module, value)
+            Functor(interns.intern("sort_by"), 2), FFIProcedure::Native(
+                box |_, _, _, value| _sort_by(value)
             )
         );
     }
 }
 
-fn _print(ok: Intern, interns: &Interns, executable: &Executable1, value: Value) -> Value {
-    match &value {
-        Value::Compound(_, v) if v.len() == 1 => {
-            _really_print(interns, executable, &v[0]);
-            print!("\n");
-            io::stdout().flush().unwrap();
-        }
+fn _print(shown: Shown) -> io::Result<()> {
+    println!("{}", shown.0);
+    io::stdout().flush()
+}
+
+// a value as `print` shows it
+struct Shown(String);
+
+impl FromValue for Shown {
+    fn from_value(interns: &Interns, value: Value) -> Result<Self, NativeError> {
+        let mut shown = String::new();
+        show(interns, &value, &mut shown);
+        Ok(Shown(shown))
+    }
+}
+
+// sort_by(v[...], @less): a stable sort, where `call @less(@a, @b)` says whether @a goes before @b
+fn _sort_by<H: 'static>(value: Value) -> Result<NativeStep<H>, NativeError> {
+    let mut args = match value {
+        Value::Compound(_, args) => unshare(args),
         _ => unreachable!(),
+    };
+    let (values, less) = match (args.remove(0), args.remove(0)) {
+        (Value::Vector(values), less @ Value::Compound(..)) => (unshare(values), less),
+        _ => { return Err(NativeError::new("sort_by takes a vector and something to call")); }
+    };
+    let width = 1;
+    let into = Vec::with_capacity(values.len());
+    SortBy { less, width, from: values, into, lo: 0, i: 0, j: width }.next()
+}
+
+// A bottom-up merge sort, one comparison per step: merging runs of `width` from `from` into `into`,
+// with `i` and `j` the next elements of the left and right runs that start at `lo`
+struct SortBy {
+    less: Value,
+    width: usize,
+    from: Vec<Value>,
+    into: Vec<Value>,
+    lo: usize,
+    i: usize,
+    j: usize,
+}
+
+impl SortBy {
+    fn next<H: 'static>(mut self) -> Result<NativeStep<H>, NativeError> {
+        let n = self.from.len();
+        loop {
+            if self.width >= n {
+                return Ok(NativeStep::Done(Value::vector(self.from)));
+            }
+            let mid = n.min(self.lo + self.width);
+            let hi = n.min(self.lo + 2 * self.width);
+
+            if self.i < mid && self.j < hi {
+                // right before left only if it's strictly less, so equal elements keep their order
+                let call = match self.less.clone() {
+                    Value::Compound(f, mut args) => {
+                        Rc::make_mut(&mut args).extend(vec![self.from[self.j].clone(), self.from[self.i].clone()]);
+                        Value::Compound(f, args)
+                    }
+                    _ => unreachable!(),
+                };
+                return Ok(NativeStep::Call(call, box move |_, _, _, answer| {
+                    match answer {
+                        Value::Bool(true) => { self.into.push(self.from[self.j].clone()); self.j += 1; }
+                        Value::Bool(false) => { self.into.push(self.from[self.i].clone()); self.i += 1; }
+                        _ => { return Err(NativeError::new("sort_by's comparison has to return a bool")); }
+                    }
+                    self.next()
+                }));
+            }
+
+            // one run is used up: the rest of the other follows in order
+            self.into.extend(self.from[self.i..mid].iter().cloned());
+            self.into.extend(self.from[self.j..hi].iter().cloned());
+
+            self.lo = hi;
+            if self.lo >= n {
+                self.from = mem::replace(&mut self.into, Vec::with_capacity(n));
+                self.width *= 2;
+                self.lo = 0;
+            }
+            self.i = self.lo;
+            self.j = n.min(self.lo + self.width);
+        }
     }
-    Value::Compound(ok, vec![])
 }
 
 // TODO: Take interns from an external source too.
-fn _really_print(interns: &Interns, executable: &Executable1, value: &Value) {
+pub fn show(interns: &Interns, value: &Value, out: &mut String) {
     match value {
-        Value::Bool(tf) => print!("{}", tf),
+        Value::Bool(tf) => out.push_str(&tf.to_string()),
         Value::Compound(x, xs) => {
             match interns.to_string(*x) {
-                None => print!("#{}", x.raw()),
-                Some(s) => print!("{}", s),
+                None => out.push_str(&format!("#{}", x.raw())),
+                Some(s) => out.push_str(s),
             }
             if xs.len() > 0 {
-                print!("(");
+                out.push('(');
                 for (i, x) in xs.iter().enumerate() {
                     if i > 0 {
-                        print!(", ");
+                        out.push_str(", ");
                     }
-                    _really_print(interns, executable, x);
+                    show(interns, x, out);
                 }
-                print!(")");
+                out.push(')');
             }
         }
-        Value::Integer(i) => print!("{}", i),
+        Value::Integer(i) => out.push_str(&i.to_string()),
+        Value::Pid(pid) => out.push_str(&format!("<{}>", pid.0)),
         Value::Set(xs) => {
-            print!("s[");
+            out.push_str("s[");
             for (i, x) in xs.iter().enumerate() {
                 if i > 0 {
-                    print!(", ");
+                    out.push_str(", ");
                 }
-                _really_print(interns, executable, x);
+                show(interns, x, out);
             }
-            print!("]");
+            out.push(']');
         }
         Value::Vector(xs) => {
-            print!("v[");
+            out.push_str("v[");
             for (i, x) in xs.iter().enumerate() {
                 if i > 0 {
-                    print!(", ");
+                    out.push_str(", ");
                 }
-                _really_print(interns, executable, x);
+                show(interns, x, out);
+            }
+            out.push(']');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::Scheduler;
+    use crate::testing::{call_with, compile, compile_library, finished, run};
+    use crate::vm::VM;
+
+    const SORTS: &str = "
+        pub fn nums { ret call sort_by(v[5, 3, 9, 1, 4, 1, 8, 2, 7], fn(@a, @b) { ret @a < @b. }). }
+        pub fn desc { ret call sort_by(v[5, 3, 9, 1, 4], fn(@a, @b) { ret @b < @a. }). }
+        pub fn stable { ret call sort_by(v[p(2, a), p(1, b), p(2, c), p(1, d), p(0, e)], fn(p(@x, @i), p(@y, @j)) { ret @x < @y. }). }
+        pub fn empty { ret call sort_by(v[], fn(@a, @b) { ret 1 < 2. }). }
+        pub fn one { ret call sort_by(v[x], fn(@a, @b) { ret 1 < 2. }). }
+        fn by_key(@k, p(@x, @i), p(@y, @j)) { ret @x < @y. }
+        pub fn extended { ret call sort_by(v[p(3, a), p(1, b)], by_key(0)). }
+        pub fn inner { ret call sort_by(v[v[3, 1, 2], v[0]], fn(@a, @b) { let @s = call sort_by(@a, fn(@x, @y) { ret @x < @y. }). ret 1 < 0. }). }
+        pub fn bad_answer { ret call sort_by(v[1, 2], fn(@a, @b) { ret 1. }). }
+        pub fn yields { ret call sort_by(v[2, 1, 3], fn(@a, @b) { ret yield @a < @b. }). }
+    ";
+
+    #[test]
+    fn sort_by() {
+        assert_eq!(run(SORTS, "nums"), "v[1, 1, 2, 3, 4, 5, 7, 8, 9]");
+        assert_eq!(run(SORTS, "desc"), "v[9, 5, 4, 3, 1]");
+        assert_eq!(run(SORTS, "stable"), "v[p(0, e), p(1, b), p(1, d), p(2, a), p(2, c)]");
+        assert_eq!(run(SORTS, "empty"), "v[]");
+        assert_eq!(run(SORTS, "one"), "v[x]");
+        assert_eq!(run(SORTS, "extended"), "v[p(1, b), p(3, a)]");
+        assert_eq!(run(SORTS, "inner"), "v[v[3, 1, 2], v[0]]");
+        assert!(run(SORTS, "bad_answer").contains("sort_by's comparison has to return a bool"));
+    }
+
+    #[test]
+    fn sort_by_stops_like_anything_else() {
+        let (interns, code) = compile(&[SORTS]).unwrap();
+        let call = |name| crate::primitive::Value::compound(interns.to_intern(name).unwrap(), vec![]);
+
+        // a comparison can yield, and the sort picks up where it left off
+        let mut vm = VM::go(&interns, &code, &mut (), call("yields")).unwrap();
+        let mut asked = 0;
+        loop {
+            vm.run(&interns, &mut (), usize::MAX);
+            match &vm {
+                VM::Yielded(_, answer) => { let answer = answer.clone(); asked += 1; vm.resume(answer); }
+                _ => break,
             }
-            print!("]");
         }
+        assert!(asked >= 2);
+        assert_eq!(crate::testing::finished(&interns, vm), "v[1, 2, 3]");
+
+        // and run out of fuel
+        let mut vm = VM::go(&interns, &code, &mut (), call("nums")).unwrap();
+        let mut slices = 0;
+        loop {
+            vm.refuel(5);
+            vm.run(&interns, &mut (), usize::MAX);
+            match &vm { VM::Suspended(_) => slices += 1, _ => break }
+        }
+        assert!(slices > 10);
+        assert_eq!(crate::testing::finished(&interns, vm), "v[1, 1, 2, 3, 4, 5, 7, 8, 9]");
+    }
+
+    // what a game might hand its scripts
+    #[derive(Debug, Default)]
+    struct Game {
+        hp: i64,
+        hits: Vec<i64>,
     }
-}
\ No newline at end of file
+
+    struct GameLibrary;
+
+    impl Library<Game> for GameLibrary {
+        fn add_functions(&self, interns: &mut Interns, procedures: &mut HashMap<Functor<Intern>, FFIProcedure<Game>>) {
+            // hurt(n): takes n hp, and says what's left
+            procedures.insert(Functor(interns.intern("hurt"), 1), FFIProcedure::Native(box |interns, _, game, call| {
+                let (n,): (i64,) = typed::args(interns, call)?;
+                game.hp -= n;
+                game.hits.push(n);
+                Ok(NativeStep::Done(Value::Integer(game.hp)))
+            }));
+            procedures.insert(Functor(interns.intern("hp"), 0), FFIProcedure::Native(box |_, _, game, _| {
+                Ok(NativeStep::Done(Value::Integer(game.hp)))
+            }));
+        }
+    }
+
+    #[test]
+    fn host_context() {
+        let src = "
+            pub fn fight { let @a = call hurt(3). let @b = call hurt(4). ret v[@a, @b]. }
+            pub fn ask { let @a = call hurt(1). let @n = yield hurt_me. ret v[@a, call hurt(@n), call hp]. }
+            pub fn both { let @p = spawn call fight. ret call hurt(1). }
+        ";
+        let (interns, code) = compile_library(&[src], GameLibrary).unwrap();
+        let mut game = Game { hp: 20, hits: vec![] };
+        assert_eq!(call_with(&interns, &code, &mut game, "fight"), "v[17, 13]");
+        assert_eq!(game.hp, 13);
+        assert_eq!(game.hits, vec![3, 4]);
+
+        // the host can change it while the script is yielded, and the script sees that
+        let ask = Value::compound(interns.to_intern("ask").unwrap(), vec![]);
+        let mut game = Game { hp: 10, hits: vec![] };
+        let mut vm = VM::go(&interns, &code, &mut game, ask).unwrap();
+        vm.run(&interns, &mut game, usize::MAX);
+        assert_eq!(game.hp, 9);
+        match &vm { VM::Yielded(_, value) => assert_eq!(crate::testing::show(&interns, value), "hurt_me"), other => panic!("{:?}", other) }
+        game.hp = 100;
+        vm.resume(Value::Integer(5));
+        vm.run(&interns, &mut game, usize::MAX);
+        assert_eq!(finished(&interns, vm), "v[9, 95, 95]");
+        assert_eq!(game.hits, vec![1, 5]);
+
+        // every process in a scheduler gets the same one
+        let both = Value::compound(interns.to_intern("both").unwrap(), vec![]);
+        let mut game = Game { hp: 20, hits: vec![] };
+        let mut sched = Scheduler::new(&interns, &code, 3);
+        let pid = sched.spawn(&mut game, both).unwrap();
+        sched.join(&mut game, pid).unwrap().unwrap();
+        sched.run(&mut game);
+        assert_eq!(game.hp, 12);
+        assert_eq!(game.hits.len(), 3);
+    }
+}
@@ -0,0 +1,482 @@
+use crate::errors::runtime::NativeError;
+use crate::interns::{Intern, Interns};
+use crate::irs::executable1::{FFIProcedure, NativeStep};
+use crate::primitive::{unshare, Functor, Pid, Value};
+
+use std::any::TypeId;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+#[allow(unused_imports)] // for hosts: nothing in this crate derives them outside the tests
+pub use snapshots_derive::{FromValue, IntoValue};
+
+// Conversions between values and Rust types, so a native can be an ordinary Rust function
+// (see `register`). Tuples are vectors of that length, () is `ok`, and structs and enums
+// can `#[derive(FromValue, IntoValue)]` to be compounds (see snapshots-derive).
+pub trait FromValue: Sized {
+    fn from_value(interns: &Interns, value: Value) -> Result<Self, NativeError>;
+}
+
+pub trait IntoValue {
+    fn into_value(self, interns: &Interns) -> Result<Value, NativeError>;
+
+    // Interns every name `into_value` can produce. Natives only get `&Interns`, so anything
+    // that makes compounds needs this called once up front, when the native is registered
+    fn prepare(interns: &mut Interns) { Self::prepare_with(interns, &mut Prepared::default()) }
+
+    // what `prepare` does, for the types in `prepared` only once (so recursive types stop).
+    // the one to implement
+    fn prepare_with(_interns: &mut Interns, _prepared: &mut Prepared) {}
+}
+
+// the types `prepare_with` has got to so far
+#[derive(Default)]
+pub struct Prepared(HashSet<TypeId>);
+
+impl Prepared {
+    // whether this is the first time `T` has come up
+    pub fn first<T: 'static>(&mut self) -> bool {
+        self.0.insert(TypeId::of::<T>())
+    }
+}
+
+// a native's arguments, all at once: implemented for tuples, one element per argument
+pub trait FromArgs: Sized {
+    const ARITY: usize;
+    fn from_args(interns: &Interns, args: Vec<Value>) -> Result<Self, NativeError>;
+}
+
+// The arguments of the call a native got, for natives that still take a Value:
+//     let (values, less): (Vec<Value>, Value) = args(interns, call)?;
+pub fn args<T: FromArgs>(interns: &Interns, call: Value) -> Result<T, NativeError> {
+    match call {
+        Value::Compound(_, args) => T::from_args(interns, unshare(args)),
+        _ => unreachable!(), // natives are only found by functor
+    }
+}
+
+// A Rust function that can be a native: any `Fn(A, B, ...) -> Result<R, E>`, or
+// `Fn(&mut H, A, B, ...) -> Result<R, E>` to get the host's context too, where the args
+// are FromValue, R is IntoValue, and E is shown to the script's host as the native's error.
+// `Args` only tells the two kinds apart, and is never spelled out
+pub trait TypedNative<H, Args> {
+    const ARITY: usize;
+    fn prepare(interns: &mut Interns);
+    fn call(&self, interns: &Interns, host: &mut H, args: Vec<Value>) -> Result<Value, NativeError>;
+}
+
+pub struct WithHost;
+
+// adds `native` as `name`, taking as many args as the function does
+pub fn register<H: 'static, Args: 'static, F: TypedNative<H, Args> + 'static>(
+    interns: &mut Interns,
+    procedures: &mut HashMap<Functor<Intern>, FFIProcedure<H>>,
+    name: &str,
+    native: F,
+) {
+    F::prepare(interns);
+    procedures.insert(
+        Functor(interns.intern(name), F::ARITY), FFIProcedure::Native(
+            box move |interns, _, host, call| {
+                let args = match call {
+                    Value::Compound(_, args) => unshare(args),
+                    _ => unreachable!(),
+                };
+                native.call(interns, host, args).map(NativeStep::Done)
+            }
+        )
+    );
+}
+
+// for error messages: what kind of value it was
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Integer(_) => "an integer",
+        Value::Bool(_) => "a bool",
+        Value::Compound(..) => "a compound",
+        Value::Vector(_) => "a vector",
+        Value::Set(_) => "a set",
+        Value::Pid(_) => "a pid",
+    }
+}
+
+fn expected(what: &str, got: &Value) -> NativeError {
+    NativeError::new(format!("expected {}, got {}", what, kind(got)))
+}
+
+// converts one of several values, saying which one it was if it's wrong
+pub fn nth<T: FromValue>(interns: &Interns, what: &str, value: Value) -> Result<T, NativeError> {
+    T::from_value(interns, value).map_err(|e| NativeError::new(format!("{}: {}", what, e.message)))
+}
+
+// whether `value` is `head(...)` with `n` args
+pub fn is_compound(interns: &Interns, head: &str, n: usize, value: &Value) -> bool {
+    match value {
+        Value::Compound(f, args) => args.len() == n && interns.to_string(*f) == Some(head),
+        _ => false,
+    }
+}
+
+// the args of `value`, if it's `head(...)` with `n` of them
+pub fn compound_args(interns: &Interns, head: &str, n: usize, value: Value) -> Result<Vec<Value>, NativeError> {
+    if !is_compound(interns, head, n, &value) {
+        return Err(expected(&format!("{} with {} args", head, n), &value));
+    }
+    match value {
+        Value::Compound(_, args) => Ok(unshare(args)),
+        _ => unreachable!(),
+    }
+}
+
+pub fn head(interns: &Interns, head: &str) -> Result<Intern, NativeError> {
+    interns.to_intern(head).ok_or_else(|| {
+        NativeError::new(format!("`{}` was never interned: call IntoValue::prepare when registering", head))
+    })
+}
+
+impl FromValue for Value {
+    fn from_value(_: &Interns, value: Value) -> Result<Self, NativeError> { Ok(value) }
+}
+
+impl IntoValue for Value {
+    fn into_value(self, _: &Interns) -> Result<Value, NativeError> { Ok(self) }
+}
+
+impl FromValue for i64 {
+    fn from_value(_: &Interns, value: Value) -> Result<Self, NativeError> {
+        match value {
+            Value::Integer(i) => Ok(i),
+            other => Err(expected("an integer", &other)),
+        }
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self, _: &Interns) -> Result<Value, NativeError> { Ok(Value::Integer(self)) }
+}
+
+impl FromValue for bool {
+    fn from_value(_: &Interns, value: Value) -> Result<Self, NativeError> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            other => Err(expected("a bool", &other)),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self, _: &Interns) -> Result<Value, NativeError> { Ok(Value::Bool(self)) }
+}
+
+impl FromValue for Pid {
+    fn from_value(_: &Interns, value: Value) -> Result<Self, NativeError> {
+        match value {
+            Value::Pid(pid) => Ok(pid),
+            other => Err(expected("a pid", &other)),
+        }
+    }
+}
+
+impl IntoValue for Pid {
+    fn into_value(self, _: &Interns) -> Result<Value, NativeError> { Ok(Value::Pid(self)) }
+}
+
+impl IntoValue for () {
+    fn into_value(self, interns: &Interns) -> Result<Value, NativeError> {
+        Ok(Value::compound(head(interns, "ok")?, vec![]))
+    }
+
+    fn prepare_with(interns: &mut Interns, _: &mut Prepared) { interns.intern("ok"); }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(interns: &Interns, value: Value) -> Result<Self, NativeError> {
+        match value {
+            Value::Vector(values) => {
+                unshare(values).into_iter().enumerate()
+                    .map(|(i, v)| nth(interns, &format!("element {}", i), v))
+                    .collect()
+            }
+            other => Err(expected("a vector", &other)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self, interns: &Interns) -> Result<Value, NativeError> {
+        let values: Result<Vec<Value>, NativeError> = self.into_iter().map(|v| v.into_value(interns)).collect();
+        Ok(Value::vector(values?))
+    }
+
+    fn prepare_with(interns: &mut Interns, prepared: &mut Prepared) { T::prepare_with(interns, prepared); }
+}
+
+impl<T: FromValue + Ord> FromValue for BTreeSet<T> {
+    fn from_value(interns: &Interns, value: Value) -> Result<Self, NativeError> {
+        match value {
+            Value::Set(values) => {
+                unshare(values).into_iter()
+                    .map(|v| nth(interns, "element", v))
+                    .collect()
+            }
+            other => Err(expected("a set", &other)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for BTreeSet<T> {
+    fn into_value(self, interns: &Interns) -> Result<Value, NativeError> {
+        let values: Result<BTreeSet<Value>, NativeError> = self.into_iter().map(|v| v.into_value(interns)).collect();
+        Ok(Value::set(values?))
+    }
+
+    fn prepare_with(interns: &mut Interns, prepared: &mut Prepared) { T::prepare_with(interns, prepared); }
+}
+
+// so a derived enum can hold itself
+impl<T: FromValue> FromValue for Box<T> {
+    fn from_value(interns: &Interns, value: Value) -> Result<Self, NativeError> { T::from_value(interns, value).map(Box::new) }
+}
+
+impl<T: IntoValue> IntoValue for Box<T> {
+    fn into_value(self, interns: &Interns) -> Result<Value, NativeError> { (*self).into_value(interns) }
+
+    fn prepare_with(interns: &mut Interns, prepared: &mut Prepared) { T::prepare_with(interns, prepared); }
+}
+
+impl FromArgs for () {
+    const ARITY: usize = 0;
+    fn from_args(_: &Interns, args: Vec<Value>) -> Result<Self, NativeError> {
+        if !args.is_empty() { return Err(NativeError::new("expected no arguments")); }
+        Ok(())
+    }
+}
+
+impl<H, F, R, E> TypedNative<H, ()> for F
+    where F: Fn() -> Result<R, E>, R: IntoValue, E: fmt::Display
+{
+    const ARITY: usize = 0;
+    fn prepare(interns: &mut Interns) { R::prepare(interns); }
+    fn call(&self, interns: &Interns, _: &mut H, args: Vec<Value>) -> Result<Value, NativeError> {
+        <()>::from_args(interns, args)?;
+        self().map_err(|e| NativeError::new(e.to_string()))?.into_value(interns)
+    }
+}
+
+impl<H, F, R, E> TypedNative<H, (WithHost,)> for F
+    where F: Fn(&mut H) -> Result<R, E>, R: IntoValue, E: fmt::Display
+{
+    const ARITY: usize = 0;
+    fn prepare(interns: &mut Interns) { R::prepare(interns); }
+    fn call(&self, interns: &Interns, host: &mut H, args: Vec<Value>) -> Result<Value, NativeError> {
+        <()>::from_args(interns, args)?;
+        self(host).map_err(|e| NativeError::new(e.to_string()))?.into_value(interns)
+    }
+}
+
+// one set of impls per arity: the tuple as args, the tuple as a vector, and functions taking those args
+macro_rules! tuple_impls {
+    ($n:expr; $($A:ident),*) => {
+        impl<$($A: FromValue),*> FromArgs for ($($A,)*) {
+            const ARITY: usize = $n;
+            #[allow(non_snake_case)]
+            fn from_args(interns: &Interns, args: Vec<Value>) -> Result<Self, NativeError> {
+                if args.len() != $n {
+                    return Err(NativeError::new(format!("expected {} arguments, got {}", $n, args.len())));
+                }
+                let mut args = args.into_iter().enumerate();
+                $(
+                    let (i, value) = args.next().unwrap();
+                    let $A = nth(interns, &format!("argument {}", i + 1), value)?;
+                )*
+                Ok(($($A,)*))
+            }
+        }
+
+        impl<$($A: FromValue),*> FromValue for ($($A,)*) {
+            fn from_value(interns: &Interns, value: Value) -> Result<Self, NativeError> {
+                match value {
+                    Value::Vector(values) if values.len() == $n => Self::from_args(interns, unshare(values)),
+                    other => Err(expected(&format!("a vector of {}", $n), &other)),
+                }
+            }
+        }
+
+        impl<$($A: IntoValue),*> IntoValue for ($($A,)*) {
+            #[allow(non_snake_case)]
+            fn into_value(self, interns: &Interns) -> Result<Value, NativeError> {
+                let ($($A,)*) = self;
+                Ok(Value::vector(vec![$($A.into_value(interns)?),*]))
+            }
+
+            fn prepare_with(interns: &mut Interns, prepared: &mut Prepared) { $($A::prepare_with(interns, prepared);)* }
+        }
+
+        impl<H, F, R, E, $($A),*> TypedNative<H, ($($A,)*)> for F
+            where F: Fn($($A),*) -> Result<R, E>, ($($A,)*): FromArgs, R: IntoValue, E: fmt::Display
+        {
+            const ARITY: usize = $n;
+            fn prepare(interns: &mut Interns) { R::prepare(interns); }
+            #[allow(non_snake_case)]
+            fn call(&self, interns: &Interns, _: &mut H, args: Vec<Value>) -> Result<Value, NativeError> {
+                let ($($A,)*) = <($($A,)*)>::from_args(interns, args)?;
+                self($($A),*).map_err(|e| NativeError::new(e.to_string()))?.into_value(interns)
+            }
+        }
+
+        impl<H, F, R, E, $($A),*> TypedNative<H, (WithHost, $($A,)*)> for F
+            where F: Fn(&mut H, $($A),*) -> Result<R, E>, ($($A,)*): FromArgs, R: IntoValue, E: fmt::Display
+        {
+            const ARITY: usize = $n;
+            fn prepare(interns: &mut Interns) { R::prepare(interns); }
+            #[allow(non_snake_case)]
+            fn call(&self, interns: &Interns, host: &mut H, args: Vec<Value>) -> Result<Value, NativeError> {
+                let ($($A,)*) = <($($A,)*)>::from_args(interns, args)?;
+                self(host, $($A),*).map_err(|e| NativeError::new(e.to_string()))?.into_value(interns)
+            }
+        }
+    }
+}
+
+tuple_impls!(1; A);
+tuple_impls!(2; A, B);
+tuple_impls!(3; A, B, C);
+tuple_impls!(4; A, B, C, D);
+tuple_impls!(5; A, B, C, D, E1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irs::executable1::Executable1;
+    use crate::testing::{run, show};
+
+    #[derive(Debug, PartialEq, FromValue, IntoValue)]
+    #[compound("point")]
+    struct Point { x: i64, y: i64 }
+
+    #[derive(Debug, PartialEq, FromValue, IntoValue)]
+    struct Pair(i64, bool);
+
+    #[derive(Debug, PartialEq, FromValue, IntoValue)]
+    struct Nothing;
+
+    #[derive(Debug, PartialEq, FromValue, IntoValue)]
+    enum Shape {
+        #[compound("circle")]
+        Circle { at: Point, r: i64 },
+        Polygon(Vec<Point>),
+        Empty,
+    }
+
+    // recursive, through a Vec
+    #[derive(Debug, PartialEq, FromValue, IntoValue)]
+    #[compound("tree")]
+    struct Tree { label: i64, kids: Vec<Tree> }
+
+    // what `value` comes to once it's been through a Value and back, and the Value shown
+    fn round_trip<T: FromValue + IntoValue>(value: T) -> (T, String) {
+        let mut interns = Interns::new(0);
+        T::prepare(&mut interns);
+        let converted = value.into_value(&interns).unwrap();
+        let shown = show(&interns, &converted);
+        (T::from_value(&interns, converted).unwrap(), shown)
+    }
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(round_trip(-7i64), (-7, "-7".to_owned()));
+        assert_eq!(round_trip(true), (true, "true".to_owned()));
+        assert_eq!(round_trip(vec![1i64, 2, 3]), (vec![1, 2, 3], "v[1, 2, 3]".to_owned()));
+        let set: BTreeSet<i64> = vec![3, 1, 2].into_iter().collect();
+        assert_eq!(round_trip(set.clone()), (set, "s[1, 2, 3]".to_owned()));
+        assert_eq!(round_trip((1i64, false, vec![true])), ((1, false, vec![true]), "v[1, false, v[true]]".to_owned()));
+        let mut interns = Interns::new(0);
+        <()>::prepare(&mut interns);
+        assert_eq!(show(&interns, &().into_value(&interns).unwrap()), "ok");
+
+        assert_eq!(round_trip(Point { x: 1, y: 2 }), (Point { x: 1, y: 2 }, "point(1, 2)".to_owned()));
+        assert_eq!(round_trip(Pair(3, true)), (Pair(3, true), "pair(3, true)".to_owned()));
+        assert_eq!(round_trip(Nothing), (Nothing, "nothing".to_owned()));
+        let circle = Shape::Circle { at: Point { x: 0, y: 1 }, r: 5 };
+        assert_eq!(round_trip(circle), (Shape::Circle { at: Point { x: 0, y: 1 }, r: 5 }, "circle(point(0, 1), 5)".to_owned()));
+        let polygon = Shape::Polygon(vec![Point { x: 0, y: 0 }]);
+        assert_eq!(round_trip(polygon), (Shape::Polygon(vec![Point { x: 0, y: 0 }]), "polygon(v[point(0, 0)])".to_owned()));
+        assert_eq!(round_trip(Shape::Empty), (Shape::Empty, "empty".to_owned()));
+        let tree = Tree { label: 1, kids: vec![Tree { label: 2, kids: vec![] }] };
+        assert_eq!(round_trip(tree).1, "tree(1, v[tree(2, v[])])");
+    }
+
+    #[test]
+    fn prepare_interns_fields_even_if_the_head_already_was() {
+        let mut interns = Interns::new(0);
+        interns.intern("circle");
+        Shape::prepare(&mut interns);
+        assert!(interns.to_intern("point").is_some());
+    }
+
+    #[test]
+    fn conversion_errors() {
+        let mut interns = Interns::new(0);
+        Shape::prepare(&mut interns);
+        let message = |e: NativeError| e.message;
+        assert_eq!(message(i64::from_value(&interns, Value::Bool(true)).unwrap_err()), "expected an integer, got a bool");
+        let wrong = Value::vector(vec![Value::Integer(1), Value::Bool(true)]);
+        assert_eq!(message(Vec::<i64>::from_value(&interns, wrong).unwrap_err()), "element 1: expected an integer, got a bool");
+        let point = Value::compound(interns.to_intern("point").unwrap(), vec![Value::Integer(1)]);
+        assert_eq!(message(Point::from_value(&interns, point).unwrap_err()), "expected point with 2 args, got a compound");
+        assert_eq!(message(Shape::from_value(&interns, Value::Integer(1)).unwrap_err()), "expected one of circle, polygon, empty");
+    }
+
+    struct Has;
+
+    impl<H: 'static> crate::library::Library<H> for Has {
+        fn add_functions(&self, interns: &mut Interns, procedures: &mut HashMap<Functor<Intern>, FFIProcedure<H>>) {
+            register(interns, procedures, "has", |x: i64, xs: Vec<i64>| -> Result<bool, String> { Ok(xs.contains(&x)) });
+            register(interns, procedures, "origin", || -> Result<Point, String> { Ok(Point { x: 0, y: 0 }) });
+            register(interns, procedures, "fails", |x: i64| -> Result<(), String> { Err(format!("no {}", x)) });
+        }
+    }
+
+    fn run_has(src: &str) -> String {
+        let parsed = crate::parser::parse_module(src).unwrap();
+        let mut interns = Interns::new(0);
+        let code = parsed.compile(&mut interns, (crate::library::Standard, Has)).unwrap();
+        let call = Value::compound(interns.intern("main"), vec![]);
+        let mut vm = crate::vm::VM::go(&interns, &code, &mut (), call).unwrap();
+        while vm.is_running() { vm.run(&interns, &mut (), usize::MAX); }
+        match vm {
+            crate::vm::VM::Succeeded(value, _) => show(&interns, &value),
+            crate::vm::VM::Failed(crate::errors::runtime::Error::Native { message, .. }) => message,
+            _ => panic!("didn't finish"),
+        }
+    }
+
+    #[test]
+    fn registered_natives() {
+        assert_eq!(run_has("pub fn main { ret v[call has(2, v[1, 2]), call has(3, v[1, 2]), call origin]. }"), "v[true, false, point(0, 0)]");
+        assert_eq!(run_has("pub fn main { ret call has(a, v[1, 2]). }"), "argument 1: expected an integer, got a compound");
+        assert_eq!(run_has("pub fn main { ret call has(1, v[1, b]). }"), "argument 2: element 1: expected an integer, got a compound");
+        assert_eq!(run_has("pub fn main { ret call fails(4). }"), "no 4");
+        // a call with the wrong number of args is a different functor, so it's no procedure at all
+        assert_eq!(run("pub fn main { ret call print(1, 2). }", "main"), "failed: NoSuchProcedure");
+    }
+
+    #[test]
+    fn registered_arity() {
+        let mut interns = Interns::new(0);
+        let mut procedures = HashMap::new();
+        crate::library::Library::<()>::add_functions(&Has, &mut interns, &mut procedures);
+        let has = interns.to_intern("has").unwrap();
+        let code: Executable1 = Executable1 {
+            procedures: HashMap::new(), constants: vec![], constant_names: HashMap::new(), global_names: HashMap::new(), types: None,
+        };
+        let native = match &procedures[&Functor(has, 2)] {
+            FFIProcedure::Native(native) => native,
+            _ => unreachable!(),
+        };
+        match native(&interns, &code, &mut (), Value::compound(has, vec![Value::Integer(1)])) {
+            Err(e) => assert_eq!(e.message, "expected 2 arguments, got 1"),
+            Ok(_) => panic!("took the wrong number of arguments"),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::errors::loader::*;
+use crate::irs::ast1::Module;
+use crate::parser::parse_module;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EXTENSION: &str = "pter";
+
+// Finds `import a::b.` as a/b.pter in the first directory of the search path that has it.
+pub struct Loader {
+    search_path: Vec<PathBuf>,
+}
+
+impl Loader {
+    pub fn new(search_path: Vec<PathBuf>) -> Loader {
+        Loader { search_path }
+    }
+
+    // The file at `root`, then everything it imports, transitively.
+    // Each module is only loaded once however many times it's imported, so cycles are fine.
+    pub fn load(&self, root: &Path) -> Loading<Vec<Module>> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![];
+
+        let module = self.load_file(root)?;
+        if let Some(name) = &module.name { seen.insert(name.clone()); }
+        pending.extend(module.imports.iter().cloned());
+        let mut modules = vec![module];
+
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name.clone()) { continue; }
+
+            let path = self.find(&name)?;
+            let module = self.load_file(&path)?;
+            if module.name.as_ref() != Some(&name) {
+                return Err(Error::WrongModuleName(path, name, module.name));
+            }
+            pending.extend(module.imports.iter().cloned());
+            modules.push(module);
+        }
+        Ok(modules)
+    }
+
+    fn find(&self, name: &str) -> Loading<PathBuf> {
+        let relative = name.split("::").collect::<PathBuf>().with_extension(EXTENSION);
+        for dir in self.search_path.iter() {
+            let candidate = dir.join(&relative);
+            if candidate.is_file() { return Ok(candidate); }
+        }
+        Err(Error::NotFound(name.to_owned()))
+    }
+
+    fn load_file(&self, path: &Path) -> Loading<Module> {
+        let source = fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        match parse_module(&source) {
+            Ok(module) => Ok(module),
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                Err(Error::Parse(path.to_owned(), nom::error::convert_error(&source, e)))
+            }
+            Err(nom::Err::Incomplete(_)) => {
+                Err(Error::Parse(path.to_owned(), "unexpected end of input".to_owned()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory holding `files` (path, source), for one test
+    fn dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("snapshots-loader-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, source) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    fn names(modules: &[Module]) -> Vec<String> {
+        modules.iter().map(|m| m.name.clone().unwrap_or_else(|| "(root)".to_owned())).collect()
+    }
+
+    #[test]
+    fn imports() {
+        let dir = dir("imports", &[
+            ("main.pter", "import shapes::circle. import util. pub fn main { ret 1. }"),
+            ("shapes/circle.pter", "module shapes::circle. import util. pub fn area { ret 3. }"),
+            ("util.pter", "module util. pub fn id(@x) { ret @x. }"),
+        ]);
+        let modules = Loader::new(vec![dir.clone()]).load(&dir.join("main.pter")).unwrap();
+        // util only once, however many import it
+        let mut loaded = names(&modules);
+        loaded.sort();
+        assert_eq!(loaded, vec!["(root)", "shapes::circle", "util"]);
+        assert_eq!(names(&modules)[0], "(root)");
+    }
+
+    #[test]
+    fn search_path() {
+        let first = dir("search-first", &[("main.pter", "import lib. pub fn main { ret 1. }")]);
+        let second = dir("search-second", &[("lib.pter", "module lib. pub fn f { ret 2. }")]);
+        let modules = Loader::new(vec![first.clone(), second]).load(&first.join("main.pter")).unwrap();
+        assert_eq!(names(&modules), vec!["(root)", "lib"]);
+    }
+
+    #[test]
+    fn cycles() {
+        let dir = dir("cycles", &[
+            ("main.pter", "import a. pub fn main { ret 1. }"),
+            ("a.pter", "module a. import b. pub fn f { ret 1. }"),
+            ("b.pter", "module b. import a. pub fn g { ret 2. }"),
+        ]);
+        let modules = Loader::new(vec![dir.clone()]).load(&dir.join("main.pter")).unwrap();
+        assert_eq!(names(&modules), vec!["(root)", "a", "b"]);
+    }
+
+    #[test]
+    fn errors() {
+        let dir = dir("errors", &[
+            ("missing.pter", "import nowhere. pub fn main { ret 1. }"),
+            ("misnamed.pter", "import other. pub fn main { ret 1. }"),
+            ("other.pter", "module something_else. pub fn f { ret 1. }"),
+            ("broken.pter", "pub fn main { ret"),
+        ]);
+        let loader = Loader::new(vec![dir.clone()]);
+        match loader.load(&dir.join("missing.pter")) {
+            Err(e @ Error::NotFound(_)) => assert_eq!(e.to_string(), "no module nowhere on the search path"),
+            other => panic!("{:?}", other.map(|m| names(&m))),
+        }
+        match loader.load(&dir.join("misnamed.pter")) {
+            Err(Error::WrongModuleName(_, expected, declared)) => {
+                assert_eq!(expected, "other");
+                assert_eq!(declared, Some("something_else".to_owned()));
+            }
+            other => panic!("{:?}", other.map(|m| names(&m))),
+        }
+        match loader.load(&dir.join("broken.pter")) {
+            Err(Error::Parse(path, _)) => assert_eq!(path, dir.join("broken.pter")),
+            other => panic!("{:?}", other.map(|m| names(&m))),
+        }
+        match loader.load(&dir.join("absent.pter")) {
+            Err(Error::Io(..)) => {}
+            other => panic!("{:?}", other.map(|m| names(&m))),
+        }
+    }
+}
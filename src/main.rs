@@ -5,20 +5,71 @@
 
 #[macro_use]
 extern crate nom;
+// so that what snapshots-derive generates can name this crate the same way from inside it
+extern crate self as snapshots;
 
+mod bench;
+mod check;
 mod compiler;
 mod errors;
 mod interns;
 mod irs;
 mod library;
+mod loader;
 mod parser;
 mod primitive;
 mod repl;
+mod scheduler;
+mod store;
+#[cfg(test)]
+mod testing;
+mod typer;
 mod vm;
 
+use compiler::Options;
+use irs::procedure1::OptLevel;
+
+use std::path::PathBuf;
+
 pub fn main() {
+    // snapshots [-O0 | -O1] [--check-types] [--check-opt | --bench] [files...]
+    let mut options = Options::default();
+    let mut check_opt = false;
+    let mut bench = false;
+    let mut paths = vec![];
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-O0" => { options.opt = OptLevel::O0; }
+            "-O1" => { options.opt = OptLevel::O1; }
+            "--check-types" => { options.check_types = true; }
+            "--check-opt" => { check_opt = true; }
+            "--bench" => { bench = true; }
+            _ => { paths.push(PathBuf::from(arg)); }
+        }
+    }
+
+    if check_opt {
+        if !check::check_opt(&paths) { std::process::exit(1); }
+        return;
+    }
+    if bench {
+        if !bench::bench(&paths, options.opt) { std::process::exit(1); }
+        return;
+    }
+
+    // `snapshots path/to/main.pter` loads that file and its imports, with the file's directory as the search path
+    if let Some(path) = paths.first() {
+        let mut interns = interns::Interns::new(0);
+        let compiled = check::compile_file(path, &mut interns, options).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        repl::repl_main(&interns, &compiled);
+        return;
+    }
+
     let parsed = parser::parse_module(r##"
-    fn main {
+    pub fn main {
         if let scott(@alpha, beta) = scott(alpha, delta) {
 
         }
@@ -48,10 +99,15 @@ pub fn main() {
     }
     "##); // should return r(3, dude)!!!!
 
+    let parsed = parsed.unwrap_or_else(|e| {
+        eprintln!("the built-in example doesn't parse: {:?}", e);
+        std::process::exit(1);
+    });
     let mut interns = interns::Interns::new(0);
-    let compiled = parsed.unwrap().compile(&mut interns, library::Standard);
-
-    let ready_to_run = compiled.unwrap();
+    let ready_to_run = compiler::compile_modules(vec![parsed], &mut interns, library::Standard, options).unwrap_or_else(|e| {
+        eprintln!("the built-in example doesn't compile: {:?}", e);
+        std::process::exit(1);
+    });
 
     // println!("Code: {}", ready_to_run.dump());
     repl::repl_main(&interns, &ready_to_run);
@@ -23,6 +23,14 @@ pub fn lexeme<'a, O>(f: impl Fn(&'a str) -> IResult<&'a str, O, Error>) -> impl
     }
 }
 
+pub fn spanned<'a, O>(f: impl Fn(&'a str) -> IResult<&'a str, O, Error>) -> impl Fn(&'a str) -> IResult<&'a str, (Span, O), Error> {
+    move |inp: &'a str| {
+        let start = inp.len();
+        let (inp, res) = f(inp)?;
+        Ok((inp, (Span(start, inp.len()), res)))
+    }
+}
+
 pub fn surrounded<'a, O>(l: &'a str, r: &'a str, f: impl Fn(&'a str) -> IResult<&'a str, O, Error>) -> impl Fn(&'a str) -> IResult<&'a str, O, Error> {
     move |inp| {
         let (inp, _) = lexeme(tag(l))(inp)?;
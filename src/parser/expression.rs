@@ -1,7 +1,7 @@
 use nom::{
     IResult, 
     branch::{alt},
-    combinator::{cut, opt},
+    combinator::{cut, not, opt, peek},
     bytes::complete::{take_while1, tag},
     multi,
 };
@@ -63,6 +63,12 @@ fn expression_leaf(inp: &str) -> IResult<&str, Expression, Error> {
 
         expression_variable,
         expression_call,
+        expression_lambda,
+        expression_pop,
+        expression_spawn,
+        expression_self,
+        expression_yield,
+        expression_get,
 
         expression_vector_literal,
         expression_set_literal,
@@ -83,7 +89,12 @@ fn expression_int_literal(inp: &str) -> IResult<&str, Expression, Error> {
 
 fn expression_variable(inp: &str) -> IResult<&str, Expression, Error> {
     let (inp, s) = var(inp)?;
-    Ok((inp, Expression::Variable(s)))
+    let (inp, oargs) = opt(surrounded("(", ")", multi::separated_nonempty_list(lexeme(tag(",")), expression)))(inp)?;
+
+    match oargs {
+        None => Ok((inp, Expression::Variable(s))),
+        Some(args) => Ok((inp, Expression::Apply(box Expression::Variable(s), args))),
+    }
 }
 
 fn expression_call(inp: &str) -> IResult<&str, Expression, Error> {
@@ -94,9 +105,58 @@ fn expression_call(inp: &str) -> IResult<&str, Expression, Error> {
     })(inp)
 }
 
+fn expression_lambda(inp: &str) -> IResult<&str, Expression, Error> {
+    let (inp, _) = lexeme(tag("fn"))(inp)?;
+    let (inp, args) = alt((
+        surrounded("(", ")", multi::separated_nonempty_list(lexeme(tag(",")), pattern)),
+        |inp| { let (inp, _) = peek(tag("{"))(inp)?; Ok((inp, vec![])) },
+    ))(inp)?;
+    let (inp, body) = block(inp)?;
+    Ok((inp, Expression::Lambda(args, body)))
+}
+
+fn expression_pop(inp: &str) -> IResult<&str, Expression, Error> {
+    // `pop` on its own is still a term
+    let (inp, _) = lexeme_ws(tag("pop"))(inp)?;
+    let (inp, v) = var(inp)?;
+    Ok((inp, Expression::VecPop(v)))
+}
+
+fn expression_spawn(inp: &str) -> IResult<&str, Expression, Error> {
+    let (inp, _) = lexeme_ws(tag("spawn"))(inp)?;
+    let (inp, _) = lexeme_ws(tag("call"))(inp)?;
+    cut(|inp| {
+        let (inp, term) = expression_leaf(inp)?;
+        Ok((inp, Expression::Spawn(box term)))
+    })(inp)
+}
+
+fn expression_self(inp: &str) -> IResult<&str, Expression, Error> {
+    let (inp, _) = lexeme(|inp| {
+        let (inp, _) = keyword("self")(inp)?;
+        // not the start of a compound either
+        not(tag("("))(inp)
+    })(inp)?;
+    Ok((inp, Expression::SelfPid))
+}
+
+fn expression_yield(inp: &str) -> IResult<&str, Expression, Error> {
+    // `yield` on its own is still a term
+    let (inp, _) = lexeme_ws(tag("yield"))(inp)?;
+    let (inp, term) = expression_leaf(inp)?;
+    Ok((inp, Expression::Yield(box term)))
+}
+
+fn expression_get(inp: &str) -> IResult<&str, Expression, Error> {
+    // like `yield`, `get` on its own is still a term
+    let (inp, _) = lexeme_ws(tag("get"))(inp)?;
+    let (inp, key) = expression_leaf(inp)?;
+    Ok((inp, Expression::StoreGet(box key)))
+}
+
 fn expression_compound_literal(inp: &str) -> IResult<&str, Expression, Error> {
     // TODO: Take a generalized string (quotes etc)
-    let (inp, head) = lexeme(identifier)(inp)?;
+    let (inp, head) = lexeme(qualified)(inp)?;
     let (inp, oargs) = opt(surrounded("(", ")", multi::separated_nonempty_list(lexeme(tag(",")), expression)))(inp)?;
     let args = oargs.unwrap_or_else(|| vec![]);
 
@@ -113,4 +173,27 @@ fn expression_set_literal(inp: &str) -> IResult<&str, Expression, Error> {
     let (inp, args) = surrounded("s[", "]", multi::separated_list(lexeme(tag(",")), expression))(inp)?;
 
     Ok((inp, Expression::Set(args)))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parses(src: &str) -> String {
+        match expression(src) {
+            Ok(("", expression)) => format!("{:?}", expression),
+            Ok((rest, _)) => format!("left {:?}", rest),
+            Err(_) => "error".to_owned(),
+        }
+    }
+
+    #[test]
+    fn self_pid() {
+        assert_eq!(parses("self"), "SelfPid");
+        assert_eq!(parses("self "), "SelfPid");
+        assert_eq!(parses("v[self, self]"), "Vector([SelfPid, SelfPid])");
+        assert_eq!(parses("selfish"), "Compound(\"selfish\", [])");
+        assert_eq!(parses("self_pid"), "Compound(\"self_pid\", [])");
+        assert_eq!(parses("self(1)"), "Compound(\"self\", [IntLiteral(1)])");
+    }
+}
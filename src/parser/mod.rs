@@ -7,6 +7,7 @@ mod expression;
 mod pattern;
 mod statement;
 mod structural;
+mod types;
 mod whitespace;
 mod word;
 
@@ -16,5 +17,6 @@ pub use expression::*;
 pub use pattern::*;
 pub use statement::*;
 pub use structural::*;
+pub use types::*;
 pub use whitespace::*;
 pub use word::*;
\ No newline at end of file
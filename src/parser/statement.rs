@@ -2,24 +2,64 @@ use nom::{
     IResult, 
     branch::{alt},
     combinator::{cut, opt},
-    bytes::complete::tag,
+    error::{ErrorKind, ParseError},
+    bytes::complete::{take_while1, tag},
+    multi,
 };
 
+use std::str::FromStr;
+
 use super::*;
 
 pub fn statement(inp: &str) -> IResult<&str, Statement, Error> {
-    alt((statement_let, statement_now, statement_eval, statement_if, statement_ret))(inp)
+    alt((
+        statement_let, statement_now, statement_push, statement_eval, statement_if, statement_match,
+        statement_send, statement_receive, statement_put, statement_atomic, statement_abort, statement_ret,
+    ))(inp)
 }
 
 fn statement_now(inp: &str) -> IResult<&str, Statement, Error> {
     let (inp, _) = lexeme_ws(tag("now"))(inp)?;
     cut(|inp| {
         let (inp, pat) = var(inp)?;
+        let (inp, place) = opt(alt((
+            |inp| surrounded("[", "]", expression)(inp).map(|(i, o)| (i, Place::Index(o))),
+            |inp| {
+                let (at, _) = tag(".")(inp)?;
+                let (inp, digits) = lexeme(take_while1(|i| "0123456789".contains(i)))(at)?;
+                match usize::from_str(digits) {
+                    Ok(n) => Ok((inp, Place::Arg(n))),
+                    Err(_) => Err(nom::Err::Error(ParseError::from_error_kind(at, ErrorKind::TooLarge))),
+                }
+            },
+        )))(inp)?;
         let (inp, _) = lexeme(tag("="))(inp)?;
         let (inp, expr) = expression(inp)?;
         let (inp, _) = lexeme(tag("."))(inp)?;
 
-        Ok((inp, Statement::Assign(pat, expr)))
+        Ok((inp, match place {
+            None => Statement::Assign(pat, expr),
+            Some(Place::Index(ix)) => Statement::VecSet(pat, ix, expr),
+            Some(Place::Arg(n)) => Statement::SetArg(pat, n, expr),
+        }))
+    })(inp)
+}
+
+// the part of a local that `now` writes to, if not the whole thing
+enum Place {
+    Index(Expression),
+    Arg(usize),
+}
+
+fn statement_push(inp: &str) -> IResult<&str, Statement, Error> {
+    let (inp, _) = lexeme_ws(tag("push"))(inp)?;
+    cut(|inp| {
+        let (inp, v) = var(inp)?;
+        let (inp, _) = lexeme(tag(","))(inp)?;
+        let (inp, expr) = expression(inp)?;
+        let (inp, _) = lexeme(tag("."))(inp)?;
+
+        Ok((inp, Statement::VecPush(v, expr)))
     })(inp)
 }
 
@@ -61,6 +101,73 @@ fn statement_if(inp: &str) -> IResult<&str, Statement, Error> {
     })(inp)
 }
 
+fn statement_match(inp: &str) -> IResult<&str, Statement, Error> {
+    let (inp, _) = lexeme_ws(tag("match"))(inp)?;
+    cut(|inp| {
+        let (inp, scrutinee) = expression(inp)?;
+        let (inp, arms) = surrounded("{", "}", multi::many1(match_arm))(inp)?;
+
+        Ok((inp, Statement::Match(scrutinee, arms)))
+    })(inp)
+}
+
+fn statement_send(inp: &str) -> IResult<&str, Statement, Error> {
+    let (inp, _) = lexeme_ws(tag("send"))(inp)?;
+    cut(|inp| {
+        let (inp, pid) = expression(inp)?;
+        let (inp, msg) = expression(inp)?;
+        let (inp, _) = lexeme(tag("."))(inp)?;
+
+        Ok((inp, Statement::Send(pid, msg)))
+    })(inp)
+}
+
+fn statement_receive(inp: &str) -> IResult<&str, Statement, Error> {
+    let (inp, _) = lexeme(keyword("receive"))(inp)?;
+    cut(|inp| {
+        let (inp, arms) = surrounded("{", "}", multi::many1(match_arm))(inp)?;
+
+        Ok((inp, Statement::Receive(arms)))
+    })(inp)
+}
+
+fn statement_put(inp: &str) -> IResult<&str, Statement, Error> {
+    let (inp, _) = lexeme_ws(tag("put"))(inp)?;
+    cut(|inp| {
+        let (inp, key) = expression(inp)?;
+        let (inp, _) = lexeme(tag("="))(inp)?;
+        let (inp, value) = expression(inp)?;
+        let (inp, _) = lexeme(tag("."))(inp)?;
+
+        Ok((inp, Statement::StorePut(key, value)))
+    })(inp)
+}
+
+fn statement_atomic(inp: &str) -> IResult<&str, Statement, Error> {
+    let (inp, _) = lexeme(keyword("atomic"))(inp)?;
+    cut(|inp| {
+        let (inp, body) = block(inp)?;
+
+        Ok((inp, Statement::Atomic(body)))
+    })(inp)
+}
+
+fn statement_abort(inp: &str) -> IResult<&str, Statement, Error> {
+    let (inp, _) = lexeme(keyword("abort"))(inp)?;
+    cut(|inp| {
+        let (inp, _) = lexeme(tag("."))(inp)?;
+
+        Ok((inp, Statement::Abort))
+    })(inp)
+}
+
+fn match_arm(inp: &str) -> IResult<&str, (Pattern, Block), Error> {
+    let (inp, pat) = pattern(inp)?;
+    let (inp, _) = lexeme(tag("=>"))(inp)?;
+    let (inp, body) = block(inp)?;
+    Ok((inp, (pat, body)))
+}
+
 fn statement_ret(inp: &str) -> IResult<&str, Statement, Error> {
     let (inp, _) = lexeme_ws(tag("ret"))(inp)?;
     cut(|inp| {
@@ -73,7 +180,43 @@ fn statement_ret(inp: &str) -> IResult<&str, Statement, Error> {
 
 fn block_or_if(inp: &str) -> IResult<&str, Block, Error> {
     alt((
-        |inp| statement_if(inp).map(|(i, o)| (i, Block(vec![o]))),
+        |inp| spanned(statement_if)(inp).map(|(i, o)| (i, Block(vec![o]))),
         block,
     ))(inp)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parses(src: &str) -> String {
+        match parse_repl_statement(src) {
+            Ok(statement) => format!("{:?}", statement),
+            Err(nom::Err::Failure(_)) => "failure".to_owned(),
+            Err(_) => "error".to_owned(),
+        }
+    }
+
+    #[test]
+    fn send_and_receive() {
+        assert_eq!(parses("send @p ping(self)."), "Send(Variable(\"p\"), Compound(\"ping\", [SelfPid]))");
+        assert_eq!(parses("receive { ping(@from) => { } stop => { ret ok. } }").split('(').next(), Some("Receive"));
+        assert_eq!(parses("receive{ stop => { } }").split('(').next(), Some("Receive"));
+        // a receive with no arms is one
+        assert_eq!(parses("receive { }"), "failure");
+        // names that start with `receive` aren't, so another statement can still have them
+        assert_eq!(parses("receiver(x)."), "error");
+        assert_eq!(parses("receive_all."), "error");
+        assert_eq!(parses("receive2."), "error");
+    }
+
+    #[test]
+    fn atomic_and_abort() {
+        assert!(parses("atomic { abort. }").starts_with("Atomic(Block([(Span"));
+        assert!(parses("atomic{ abort . }").ends_with("Abort)]))"));
+        assert_eq!(parses("atomic ok."), "failure");
+        assert_eq!(parses("abort"), "failure");
+        assert_eq!(parses("atomically."), "error");
+        assert_eq!(parses("aborted(x)."), "error");
+    }
+}
@@ -1,28 +1,99 @@
 use nom::{
     IResult, 
     branch::alt,
-    combinator::cut,
+    combinator::{cut, opt},
     bytes::complete::tag,
     multi,
 };
 
 use super::*;
 
+enum Item {
+    Import(String),
+    Const(ConstDecl),
+    Global(GlobalDecl),
+    Procedure(Procedure),
+    Type(TypeDecl),
+}
+
 pub fn module(inp: &str) -> IResult<&str, Module, Error> {
-    let (inp, res) = multi::many0(procedure)(inp)?;
-    Ok((inp, Module { procedures: res }))
+    let (inp, name) = opt(module_decl)(inp)?;
+    let (inp, items) = multi::many0(alt((
+        |inp| import(inp).map(|(i, o)| (i, Item::Import(o))),
+        |inp| const_decl(inp).map(|(i, o)| (i, Item::Const(o))),
+        |inp| global_decl(inp).map(|(i, o)| (i, Item::Global(o))),
+        |inp| procedure(inp).map(|(i, o)| (i, Item::Procedure(o))),
+        |inp| type_decl(inp).map(|(i, o)| (i, Item::Type(o))),
+    )))(inp)?;
+
+    let mut module = Module { name, imports: vec![], procedures: vec![], types: vec![], consts: vec![], globals: vec![] };
+    for item in items {
+        match item {
+            Item::Import(m) => module.imports.push(m),
+            Item::Const(c) => module.consts.push(c),
+            Item::Global(g) => module.globals.push(g),
+            Item::Procedure(p) => module.procedures.push(p),
+            Item::Type(t) => module.types.push(t),
+        }
+    }
+    Ok((inp, module))
+}
+
+fn module_decl(inp: &str) -> IResult<&str, String, Error> {
+    let (inp, _) = lexeme_ws(tag("module"))(inp)?;
+    cut(|inp| {
+        let (inp, name) = qualified(inp)?;
+        let (inp, _) = lexeme(tag("."))(inp)?;
+        Ok((inp, name))
+    })(inp)
+}
+
+fn import(inp: &str) -> IResult<&str, String, Error> {
+    let (inp, _) = lexeme_ws(tag("import"))(inp)?;
+    cut(|inp| {
+        let (inp, name) = qualified(inp)?;
+        let (inp, _) = lexeme(tag("."))(inp)?;
+        Ok((inp, name))
+    })(inp)
+}
+
+fn const_decl(inp: &str) -> IResult<&str, ConstDecl, Error> {
+    let (inp, _) = lexeme_ws(tag("const"))(inp)?;
+    cut(|inp| {
+        let (inp, name) = identifier(inp)?;
+        let (inp, _) = lexeme(tag("="))(inp)?;
+        let (inp, value) = expression(inp)?;
+        let (inp, _) = lexeme(tag("."))(inp)?;
+        Ok((inp, ConstDecl { name, value }))
+    })(inp)
+}
+
+fn global_decl(inp: &str) -> IResult<&str, GlobalDecl, Error> {
+    let (inp, _) = lexeme_ws(tag("global"))(inp)?;
+    cut(|inp| {
+        let (inp, name) = identifier(inp)?;
+        let (inp, _) = lexeme(tag("="))(inp)?;
+        let (inp, value) = expression(inp)?;
+        let (inp, _) = lexeme(tag("."))(inp)?;
+        Ok((inp, GlobalDecl { name, value }))
+    })(inp)
 }
 
 pub fn procedure(inp: &str) -> IResult<&str, Procedure, Error> {
+    let start = inp.len();
+    let (inp, public) = opt(lexeme_ws(tag("pub")))(inp)?;
     let (inp, _) = lexeme_ws(tag("fn"))(inp)?;
-    return cut(|inp| {
+    return cut(move |inp| {
         let (inp, identifier) = identifier(inp)?;
         let (inp, args) = alt((
             surrounded("(", ")", multi::separated_nonempty_list(lexeme(tag(",")), pattern)),
             |inp| Ok((inp, vec![])),
         ))(inp)?;
+        let span = Span(start, inp.len());
         let (inp, body) = block(inp)?;
         Ok((inp, Procedure {
+            span,
+            public: public.is_some(),
             name: identifier,
             args: args,
             body: body,
@@ -31,6 +102,6 @@ pub fn procedure(inp: &str) -> IResult<&str, Procedure, Error> {
 }
 
 pub fn block(inp: &str) -> IResult<&str, Block, Error> {
-    let (inp, sts) = surrounded("{", "}", multi::many0(statement))(inp)?;
+    let (inp, sts) = surrounded("{", "}", multi::many0(spanned(statement)))(inp)?;
     Ok((inp, Block(sts)))
 }
\ No newline at end of file
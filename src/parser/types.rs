@@ -0,0 +1,37 @@
+use nom::{
+    IResult, 
+    branch::{alt},
+    combinator::{cut, opt},
+    bytes::complete::{tag},
+    multi,
+};
+
+use super::*;
+
+pub fn type_decl(inp: &str) -> IResult<&str, TypeDecl, Error> {
+    let (inp, _) = lexeme_ws(tag("type"))(inp)?;
+    cut(|inp| {
+        let (inp, name) = identifier(inp)?;
+        let (inp, _) = lexeme(tag("="))(inp)?;
+        let (inp, variants) = multi::separated_nonempty_list(lexeme(tag("|")), variant)(inp)?;
+        let (inp, _) = lexeme(tag("."))(inp)?;
+
+        Ok((inp, TypeDecl { name, variants }))
+    })(inp)
+}
+
+fn variant(inp: &str) -> IResult<&str, Variant, Error> {
+    let (inp, name) = identifier(inp)?;
+    let (inp, oargs) = opt(surrounded("(", ")", multi::separated_nonempty_list(lexeme(tag(",")), type_expr)))(inp)?;
+    let args = oargs.unwrap_or_else(|| vec![]);
+
+    Ok((inp, Variant { name, args }))
+}
+
+pub fn type_expr(inp: &str) -> IResult<&str, TypeExpr, Error> {
+    alt((
+        |inp| surrounded("v[", "]", type_expr)(inp).map(|(i, o)| (i, TypeExpr::Vector(box o))),
+        |inp| surrounded("s[", "]", type_expr)(inp).map(|(i, o)| (i, TypeExpr::Set(box o))),
+        |inp| identifier(inp).map(|(i, o)| (i, TypeExpr::Named(o))),
+    ))(inp)
+}
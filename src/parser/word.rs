@@ -2,6 +2,7 @@ use nom::{
     IResult, 
     bytes::complete::{tag},
     character::complete::{one_of},
+    combinator::{not},
     multi,
 };
 
@@ -13,6 +14,22 @@ pub fn var(inp: &str) -> IResult<&str, String, Error> {
     identifier(inp)
 }
 
+// an identifier, possibly qualified with the module it lives in: geometry::area
+pub fn qualified(inp: &str) -> IResult<&str, String, Error> {
+    let (inp, first) = identifier(inp)?;
+    let (inp, rest) = multi::many0(|inp| {
+        let (inp, _) = lexeme(tag("::"))(inp)?;
+        identifier(inp)
+    })(inp)?;
+
+    let mut result = first;
+    for part in rest {
+        result.push_str("::");
+        result.push_str(&part);
+    }
+    Ok((inp, result))
+}
+
 pub fn identifier(inp: &str) -> IResult<&str, String, Error> {
     let (inp, first) = one_of("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_")(inp)?;
     let (inp, remaining) = lexeme(multi::many0(one_of("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_")))(inp)?;
@@ -21,4 +38,13 @@ pub fn identifier(inp: &str) -> IResult<&str, String, Error> {
     result.push(first);
     for c in remaining { result.push(c); }
     Ok((inp, result))
-}
\ No newline at end of file
+}
+// `word` itself, and not the start of some longer name (for keywords that needn't be
+// followed by whitespace, like `receive{`)
+pub fn keyword<'a>(word: &'static str) -> impl Fn(&'a str) -> IResult<&'a str, &'a str, Error> {
+    move |inp| {
+        let (inp, res) = tag(word)(inp)?;
+        let (inp, _) = not(one_of("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_0123456789"))(inp)?;
+        Ok((inp, res))
+    }
+}
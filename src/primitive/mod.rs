@@ -1,4 +1,5 @@
 use std::collections::BTreeSet;
+use std::rc::Rc;
 use crate::interns::Intern;
 
 #[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -9,17 +10,44 @@ pub enum Value {
     Integer(i64),
     Bool(bool),
 
-    Compound(Intern, Vec<Value>),
-    Vector(Vec<Value>),
-    Set(BTreeSet<Value>),
+    // Children are shared, so cloning a value only copies the top node.
+    // Anything that changes one in place goes through Rc::make_mut, which copies it first if it's shared.
+    Compound(Intern, Rc<Vec<Value>>),
+    Vector(Rc<Vec<Value>>),
+    Set(Rc<BTreeSet<Value>>),
+
+    Pid(Pid), // a process in a Scheduler: only the scheduler makes these
+}
+
+impl Value {
+    pub fn compound(f: Intern, args: Vec<Value>) -> Value {
+        Value::Compound(f, Rc::new(args))
+    }
+
+    pub fn vector(values: Vec<Value>) -> Value {
+        Value::Vector(Rc::new(values))
+    }
+
+    pub fn set(values: BTreeSet<Value>) -> Value {
+        Value::Set(Rc::new(values))
+    }
+}
+
+// the children, without copying them if nothing else has them
+pub fn unshare<T: Clone>(rc: Rc<T>) -> T {
+    Rc::try_unwrap(rc).unwrap_or_else(|shared| (*shared).clone())
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Local(pub usize);
 
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Pid(pub usize);
+
 
 #[derive(Clone, Copy, Debug)]
 pub enum Operand {
     Integer(i64),
     Bool(bool),
+    Const(usize), // index into the executable's constants, for anything that isn't an integer or a bool
 }
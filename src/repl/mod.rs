@@ -2,15 +2,20 @@ use crate::interns::Interns;
 use crate::irs::executable1::Executable1;
 use crate::parser::parse_repl_statement;
 use crate::primitive::Value;
+use crate::store::Store;
 use crate::vm::VM;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io;
 use std::io::Write;
+use std::rc::Rc;
 
 pub fn repl_main(base_interns: &Interns, loaded: &Executable1) {
     let mut scope = HashMap::new();
     let mut interns = base_interns.extend();
+    // lasts the whole session, like `scope`
+    let store = Rc::new(RefCell::new(Store::new()));
 
     loop {
         print!(">>> ");
@@ -19,16 +24,17 @@ pub fn repl_main(base_interns: &Interns, loaded: &Executable1) {
         io::stdin().read_line(&mut inp).unwrap();
 
         let parsed = parse_repl_statement(&inp).unwrap();
-        let (vars, code) = parsed.compile_repl(&mut interns).unwrap();
+        let (vars, code) = parsed.compile_repl(&mut interns, loaded).unwrap();
         // println!("Vars, code: {:?}", (&vars, &code));
 
         let mut vm = VM::start_repl(
             &code, &loaded,
             &vars, &mut scope,
         );
+        vm.use_store(store.clone());
 
         while vm.is_running() {
-            vm.update(&interns);
+            vm.run(&interns, &mut (), usize::MAX);
         }
 
         match vm {
@@ -44,6 +50,9 @@ pub fn repl_main(base_interns: &Interns, loaded: &Executable1) {
             crate::vm::VM::Failed(e) => {
                 println!("Failed: {:?}", e);
             }
+            crate::vm::VM::Yielded(_, value) => {
+                println!("Yielded: {:?}", value);
+            }
             _ => {}
         }
 
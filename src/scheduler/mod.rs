@@ -0,0 +1,292 @@
+mod post_office;
+
+pub use post_office::PostOffice;
+
+use crate::errors::runtime::*;
+use crate::interns::Interns;
+use crate::irs::executable1::Executable1;
+use crate::primitive::{Pid, Value};
+use crate::store::Store;
+use crate::vm::VM;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+// Many VMs sharing one executable, run round-robin: each runnable process gets `slice`
+// fuel per round, so one that loops forever only slows the others down.
+// Finished processes keep their result until someone joins them.
+// Whatever runs processes takes the host's context, to hand to natives (see Executable1).
+pub struct Scheduler<'a, 'proto, H = ()> {
+    interns: &'a Interns<'proto>,
+    code: &'a Executable1<H>,
+    slice: u64,
+
+    processes: BTreeMap<Pid, VM<'a, H>>,
+    post: Rc<RefCell<PostOffice>>,
+    store: Option<Rc<RefCell<Store>>>, // every process's, if the host gave one
+}
+
+impl<'a, 'proto, H> Scheduler<'a, 'proto, H> {
+    pub fn new(interns: &'a Interns<'proto>, code: &'a Executable1<H>, slice: u64) -> Self {
+        Scheduler {
+            interns, code, slice,
+            processes: BTreeMap::new(), post: Rc::new(RefCell::new(PostOffice::default())), store: None,
+        }
+    }
+
+    // starts `call` as a new process. nothing runs until the next round
+    pub fn spawn(&mut self, host: &mut H, call: Value) -> Runtime<Pid> {
+        let vm = VM::go(self.interns, self.code, host, call)?;
+        let pid = self.post.borrow_mut().register();
+        self.enroll(pid, vm);
+        Ok(pid)
+    }
+
+    // for every process, including the ones already running
+    pub fn use_store(&mut self, store: Rc<RefCell<Store>>) {
+        for vm in self.processes.values_mut() { vm.use_store(store.clone()); }
+        self.store = Some(store);
+    }
+
+    // false if there was no such process (or it was already joined).
+    // a process spawned during the round that hasn't started yet never will
+    pub fn kill(&mut self, pid: Pid) -> bool {
+        let mut post = self.post.borrow_mut();
+        post.close(pid);
+        let pending = post.unspawn(pid);
+        self.processes.remove(&pid).is_some() || pending
+    }
+
+    // Runs rounds until `pid` is done, then hands back what it returned.
+    // None if there's no such process; Deadlocked if it's waiting on a message nothing can send.
+    // Yielded if it's waiting on the host instead: `resume` it and join again.
+    // OtherYielded if it's waiting on a message and some process that could still send one
+    // is waiting on the host: resume that one, and join again.
+    pub fn join(&mut self, host: &mut H, pid: Pid) -> Option<Runtime<Value>> {
+        let mut progress = true;
+        loop {
+            match self.processes.get(&pid)? {
+                VM::Succeeded(..) | VM::Failed(_) => break,
+                VM::Yielded(..) => { return Some(Err(Error::Yielded)); }
+                VM::Waiting(_) if !progress => {
+                    let yielded = self.processes.iter().find(|(_, vm)| match vm { VM::Yielded(..) => true, _ => false });
+                    return Some(Err(match yielded {
+                        Some((other, _)) => Error::OtherYielded(*other),
+                        None => Error::Deadlocked,
+                    }));
+                }
+                _ => {}
+            }
+            progress = self.round(host);
+        }
+        match self.processes.remove(&pid)? {
+            VM::Succeeded(value, _) => Some(Ok(value)),
+            VM::Failed(e) => Some(Err(e)),
+            _ => unreachable!(),
+        }
+    }
+
+    // One slice for every process that isn't done or waiting, in pid order, then
+    // the processes spawned during the round are started.
+    // Returns whether any process can still make progress afterwards.
+    pub fn round(&mut self, host: &mut H) -> bool {
+        for (pid, vm) in self.processes.iter_mut() {
+            if self.post.borrow().has_news(*pid) { vm.wake(); }
+            if let VM::Running(_) | VM::Suspended(_) = vm {
+                vm.refuel(self.slice);
+                vm.run(self.interns, host, usize::MAX);
+                if vm.is_done() { self.post.borrow_mut().close(*pid); }
+            }
+        }
+
+        let spawned = self.post.borrow_mut().take_spawned();
+        for (pid, call) in spawned {
+            // a bad call fails the new process, not the one that spawned it
+            let vm = VM::go_spawned(self.interns, self.code, host, call).unwrap_or_else(VM::Failed);
+            self.enroll(pid, vm);
+        }
+
+        let post = self.post.borrow();
+        self.processes.iter().any(|(pid, vm)| match vm {
+            VM::Running(_) | VM::Suspended(_) => true,
+            VM::Waiting(_) => post.has_news(*pid),
+            _ => false,
+        })
+    }
+
+    // what a process yielded to the host, if that's what it's waiting on
+    pub fn yielded(&self, pid: Pid) -> Option<&Value> {
+        match self.processes.get(&pid) {
+            Some(VM::Yielded(_, value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    // false if the process wasn't waiting on the host
+    pub fn resume(&mut self, pid: Pid, answer: Value) -> bool {
+        match self.processes.get_mut(&pid) {
+            Some(vm @ VM::Yielded(..)) => { vm.resume(answer); true }
+            _ => false,
+        }
+    }
+
+    // runs rounds until no process can make progress
+    pub fn run(&mut self, host: &mut H) {
+        while self.round(host) {}
+    }
+
+    pub fn is_done(&self, pid: Pid) -> bool {
+        match self.processes.get(&pid) {
+            Some(vm) => vm.is_done(),
+            None => true,
+        }
+    }
+
+    pub fn pids(&self) -> impl Iterator<Item=Pid> + '_ {
+        self.processes.keys().cloned()
+    }
+
+    fn enroll(&mut self, pid: Pid, mut vm: VM<'a, H>) {
+        if vm.is_done() { self.post.borrow_mut().close(pid); }
+        vm.enroll(pid, self.post.clone());
+        if let Some(store) = &self.store { vm.use_store(store.clone()); }
+        self.processes.insert(pid, vm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{compile, show};
+
+    const SRC: &str = "
+        fn count(@n) { if @n == 0 { ret done. } ret call count(@n - 1). }
+        pub fn spin { ret call spin. }
+        pub fn work(@n, @tag) { let @x = call count(@n). ret v[@tag, @x]. }
+        pub fn bad { ret call nope. }
+        pub fn stuck { receive { never => { ret no. } } }
+        pub fn ask { ret yield question. }
+        pub fn notify(@to) { let @x = yield question. send @to answer(@x). ret ok. }
+        pub fn listen { receive { answer(@x) => { ret @x. } } }
+    ";
+
+    fn call(interns: &Interns, name: &str, args: Vec<Value>) -> Value {
+        Value::compound(interns.to_intern(name).unwrap(), args)
+    }
+
+    #[test]
+    fn spawn_join_kill() {
+        let (interns, code) = compile(&[SRC]).unwrap();
+        let mut sched = Scheduler::new(&interns, &code, 50);
+        let forever = sched.spawn(&mut (), call(&interns, "spin", vec![])).unwrap();
+        let a = sched.spawn(&mut (), call(&interns, "work", vec![Value::Integer(100), Value::Integer(1)])).unwrap();
+        let b = sched.spawn(&mut (), call(&interns, "work", vec![Value::Integer(10), Value::Pid(a)])).unwrap();
+        let c = sched.spawn(&mut (), call(&interns, "bad", vec![])).unwrap();
+
+        // `spin` never finishes, but only takes its slice
+        assert_eq!(show(&interns, &sched.join(&mut (), b).unwrap().unwrap()), "v[<1>, done]");
+        assert!(!sched.is_done(a));
+        assert_eq!(show(&interns, &sched.join(&mut (), a).unwrap().unwrap()), "v[1, done]");
+        // joined once, and then gone
+        assert!(sched.join(&mut (), a).is_none());
+        match sched.join(&mut (), c) { Some(Err(Error::NoSuchProcedure)) => {} other => panic!("{:?}", other) }
+
+        assert!(sched.kill(forever));
+        assert!(!sched.kill(forever));
+        assert!(sched.join(&mut (), forever).is_none());
+        assert!(!sched.round(&mut ()));
+    }
+
+    #[test]
+    fn kill_before_start() {
+        let (interns, code) = compile(&[SRC]).unwrap();
+        let mut sched = Scheduler::new(&interns, &code, 50);
+        // as a `spawn` in some process leaves it, for the end of the round
+        let pending = sched.post.borrow_mut().spawn(call(&interns, "spin", vec![]));
+        assert!(sched.kill(pending));
+        assert!(!sched.round(&mut ()));
+        assert!(sched.join(&mut (), pending).is_none());
+        assert_eq!(sched.pids().count(), 0);
+    }
+
+    #[test]
+    fn slices() {
+        let (interns, code) = compile(&[SRC]).unwrap();
+        let mut sched = Scheduler::new(&interns, &code, 50);
+        let long = sched.spawn(&mut (), call(&interns, "work", vec![Value::Integer(1000), Value::Integer(0)])).unwrap();
+        let short = sched.spawn(&mut (), call(&interns, "work", vec![Value::Integer(1), Value::Integer(0)])).unwrap();
+        assert!(sched.round(&mut ()));
+        assert!(!sched.is_done(long));
+        assert!(sched.is_done(short));
+        let mut rounds = 1;
+        while sched.round(&mut ()) { rounds += 1; }
+        assert!(sched.is_done(long));
+        assert!(rounds > 10);
+    }
+
+    #[test]
+    fn deadlocked_or_yielded() {
+        let (interns, code) = compile(&[SRC]).unwrap();
+        let mut sched = Scheduler::new(&interns, &code, 50);
+        let stuck = sched.spawn(&mut (), call(&interns, "stuck", vec![])).unwrap();
+        match sched.join(&mut (), stuck) { Some(Err(Error::Deadlocked)) => {} other => panic!("{:?}", other) }
+        assert!(sched.kill(stuck));
+
+        let ask = sched.spawn(&mut (), call(&interns, "ask", vec![])).unwrap();
+        match sched.join(&mut (), ask) { Some(Err(Error::Yielded)) => {} other => panic!("{:?}", other) }
+        assert_eq!(show(&interns, sched.yielded(ask).unwrap()), "question");
+        assert!(sched.resume(ask, Value::Integer(1)));
+        assert!(!sched.resume(ask, Value::Integer(1)));
+        assert_eq!(show(&interns, &sched.join(&mut (), ask).unwrap().unwrap()), "1");
+
+        // waiting on a process that's waiting on the host isn't a deadlock
+        let listen = sched.spawn(&mut (), call(&interns, "listen", vec![])).unwrap();
+        let notify = sched.spawn(&mut (), call(&interns, "notify", vec![Value::Pid(listen)])).unwrap();
+        match sched.join(&mut (), listen) { Some(Err(Error::OtherYielded(pid))) => assert_eq!(pid, notify), other => panic!("{:?}", other) }
+        assert!(sched.resume(notify, Value::Integer(42)));
+        assert_eq!(show(&interns, &sched.join(&mut (), listen).unwrap().unwrap()), "42");
+    }
+
+    #[test]
+    fn messages() {
+        let src = "
+            fn ponger(@n) {
+                receive {
+                    ping(@from) => { send @from pong(@n). ret call ponger(@n + 1). }
+                    stop => { ret stopped(@n). }
+                }
+            }
+            pub fn main {
+                let @p = spawn call ponger(0).
+                send @p ping(self).
+                send @p ping(self).
+                receive { pong(@a) => { } }
+                receive { pong(@b) => { } }
+                send @p stop.
+                ret v[@a, @b, @p].
+            }
+            pub fn picky {
+                send self first.
+                send self second.
+                receive { second => { } }
+                receive { @x => { ret @x. } }
+            }
+            pub fn me { ret self. }
+        ";
+        let (interns, code) = compile(&[src]).unwrap();
+        let mut sched = Scheduler::new(&interns, &code, 3);
+        let main = sched.spawn(&mut (), call(&interns, "main", vec![])).unwrap();
+        let picky = sched.spawn(&mut (), call(&interns, "picky", vec![])).unwrap();
+        let me = sched.spawn(&mut (), call(&interns, "me", vec![])).unwrap();
+
+        // main blocks on each pong until the ponger wakes it
+        assert_eq!(show(&interns, &sched.join(&mut (), main).unwrap().unwrap()), "v[0, 1, <3>]");
+        // the first message no arm matches waits for a later receive
+        assert_eq!(show(&interns, &sched.join(&mut (), picky).unwrap().unwrap()), "first");
+        assert_eq!(sched.join(&mut (), me).unwrap().unwrap(), Value::Pid(me));
+        assert_eq!(show(&interns, &sched.join(&mut (), Pid(3)).unwrap().unwrap()), "stopped(2)");
+        // outside a scheduler there's no self to be
+        assert_eq!(crate::testing::call(&interns, &code, "me"), "failed: NoScheduler");
+    }
+}
@@ -0,0 +1,80 @@
+use crate::primitive::{Pid, Value};
+
+use std::collections::{HashMap, VecDeque};
+
+// Where the processes in a Scheduler leave each other messages, and ask for new processes.
+// Every runner in the scheduler holds a handle to it, since it can't reach the scheduler itself.
+#[derive(Debug, Default)]
+pub struct PostOffice {
+    mailboxes: HashMap<Pid, Mailbox>,
+    spawned: Vec<(Pid, Value)>, // calls to start as processes once the round is over
+    next_pid: usize,
+}
+
+#[derive(Debug, Default)]
+struct Mailbox {
+    messages: VecDeque<Value>,
+    // how many messages the receive that's in progress has already turned down
+    cursor: usize,
+}
+
+impl PostOffice {
+    pub fn register(&mut self) -> Pid {
+        let pid = Pid(self.next_pid);
+        self.next_pid += 1;
+        self.mailboxes.insert(pid, Mailbox::default());
+        pid
+    }
+
+    pub fn spawn(&mut self, call: Value) -> Pid {
+        let pid = self.register();
+        self.spawned.push((pid, call));
+        pid
+    }
+
+    pub fn take_spawned(&mut self) -> Vec<(Pid, Value)> {
+        std::mem::replace(&mut self.spawned, vec![])
+    }
+
+    // a process spawned this round won't be started after all: false if there was no such spawn
+    pub fn unspawn(&mut self, pid: Pid) -> bool {
+        let before = self.spawned.len();
+        self.spawned.retain(|(p, _)| *p != pid);
+        self.spawned.len() < before
+    }
+
+    // a process that's gone (or never was) doesn't get its mail
+    pub fn send(&mut self, to: Pid, message: Value) {
+        if let Some(mailbox) = self.mailboxes.get_mut(&to) {
+            mailbox.messages.push_back(message);
+        }
+    }
+
+    // the oldest message the current receive hasn't looked at yet
+    pub fn next(&mut self, pid: Pid) -> Option<Value> {
+        let mailbox = self.mailboxes.get_mut(&pid)?;
+        let message = mailbox.messages.get(mailbox.cursor)?.clone();
+        mailbox.cursor += 1;
+        Some(message)
+    }
+
+    // the last message `next` handed out was matched: it's gone, and the next receive starts over
+    pub fn accept(&mut self, pid: Pid) {
+        if let Some(mailbox) = self.mailboxes.get_mut(&pid) {
+            mailbox.messages.remove(mailbox.cursor - 1);
+            mailbox.cursor = 0;
+        }
+    }
+
+    // whether a process waiting on a receive has something new to look at
+    pub fn has_news(&self, pid: Pid) -> bool {
+        match self.mailboxes.get(&pid) {
+            Some(mailbox) => mailbox.cursor < mailbox.messages.len(),
+            None => false,
+        }
+    }
+
+    pub fn close(&mut self, pid: Pid) {
+        self.mailboxes.remove(&pid);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::primitive::Value;
+
+use std::collections::BTreeMap;
+
+// What a program keeps between calls: terms filed under terms, for as long as the host keeps
+// the store around. `put k = v.` and `get k` are the program's side of it, and a `global` is
+// a key with a value to start from, which `get` sees until something is put there.
+// Every runner given the store holds a handle to it, so the host can hand one to several VMs.
+#[derive(Debug, Default)]
+pub struct Store {
+    values: BTreeMap<Value, Value>,
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Store::default()
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    // returns what was there before
+    pub fn put(&mut self, key: Value, value: Value) -> Option<Value> {
+        self.values.insert(key, value)
+    }
+
+    // a global goes back to its starting value
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        self.values.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&Value, &Value)> {
+        self.values.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interns::Interns;
+    use crate::irs::executable1::Executable1;
+    use crate::testing::{compile, finished, run, show};
+    use crate::vm::VM;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const SRC: &str = "
+        global hp = 10.
+        pub fn heal(@n) { put hp = get hp + @n. ret get hp. }
+        pub fn hit(@n) { put hp = get hp - @n. put seen(@n) = yes. let 1 = 2. ret ok. }
+        pub fn nested {
+            atomic {
+                put hp = 1.
+                atomic { put hp = 2. put inner = yes. abort. }
+                put outer = get hp.
+            }
+            ret get hp.
+        }
+        pub fn whole { atomic { put hp = 50. abort. } ret get hp. }
+    ";
+
+    // runs `name` a little at a time, with `store`
+    fn go(interns: &Interns, code: &Executable1, store: &Rc<RefCell<Store>>, name: &str, args: Vec<Value>) -> String {
+        let call = Value::compound(interns.to_intern(name).unwrap(), args);
+        let mut vm = VM::go(interns, code, &mut (), call).unwrap();
+        vm.use_store(store.clone());
+        while !vm.is_done() {
+            vm.refuel(3);
+            vm.run(interns, &mut (), usize::MAX);
+        }
+        finished(interns, vm)
+    }
+
+    fn key(interns: &Interns, name: &str) -> Value {
+        Value::compound(interns.to_intern(name).unwrap(), vec![])
+    }
+
+    #[test]
+    fn committed_only_on_success() {
+        let (interns, code) = compile(&[SRC]).unwrap();
+        let store = Rc::new(RefCell::new(Store::new()));
+        assert_eq!(go(&interns, &code, &store, "heal", vec![Value::Integer(5)]), "15");
+        assert_eq!(store.borrow().get(&key(&interns, "hp")), Some(&Value::Integer(15)));
+
+        assert_eq!(go(&interns, &code, &store, "hit", vec![Value::Integer(3)]), "failed: AssertionFailed");
+        assert_eq!(store.borrow().get(&key(&interns, "hp")), Some(&Value::Integer(15)));
+        assert_eq!(store.borrow().iter().count(), 1);
+    }
+
+    #[test]
+    fn abort_rolls_back_the_innermost() {
+        let (interns, code) = compile(&[SRC]).unwrap();
+        let store = Rc::new(RefCell::new(Store::new()));
+        assert_eq!(go(&interns, &code, &store, "nested", vec![]), "1");
+        assert_eq!(store.borrow().get(&key(&interns, "hp")), Some(&Value::Integer(1)));
+        assert_eq!(store.borrow().get(&key(&interns, "outer")), Some(&Value::Integer(1)));
+        assert_eq!(store.borrow().get(&key(&interns, "inner")), None);
+
+        assert_eq!(go(&interns, &code, &store, "whole", vec![]), "1");
+    }
+
+    #[test]
+    fn abort_outside_atomic() {
+        assert_eq!(run("pub fn f { abort. }", "f"), "compile error: AbortOutsideAtomic");
+        // a lambda is its own procedure, outside the block it's written in
+        assert_eq!(run("pub fn f { atomic { let @g = fn { abort. }. } ret 1. }", "f"), "compile error: AbortOutsideAtomic");
+        assert_eq!(run("pub fn f { atomic{ abort. } ret 1. }", "f"), "1");
+    }
+}
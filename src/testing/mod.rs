@@ -0,0 +1,76 @@
+use crate::compiler::{compile_modules, Options};
+use crate::errors::compiler;
+use crate::interns::Interns;
+use crate::irs::executable1::Executable1;
+use crate::library::{self, Library};
+use crate::parser::parse_module;
+use crate::primitive::Value;
+use crate::vm::VM;
+
+// What the tests share: compiling some modules, and running a procedure in them to get
+// what it returned (or how it failed) as a string, written the way `print` writes it.
+
+pub fn compile(srcs: &[&str]) -> Result<(Interns<'static>, Executable1), compiler::Error> {
+    compile_with(srcs, Options::default())
+}
+
+pub fn compile_with(srcs: &[&str], options: Options) -> Result<(Interns<'static>, Executable1), compiler::Error> {
+    let modules = srcs.iter().map(|src| parse_module(src).expect("test source doesn't parse")).collect();
+    let mut interns = Interns::new(0);
+    let code = compile_modules(modules, &mut interns, library::Standard, options)?;
+    Ok((interns, code))
+}
+
+// with a host's natives on top of the standard ones
+pub fn compile_library<H: 'static>(srcs: &[&str], natives: impl Library<H>) -> Result<(Interns<'static>, Executable1<H>), compiler::Error> {
+    let modules = srcs.iter().map(|src| parse_module(src).expect("test source doesn't parse")).collect();
+    let mut interns = Interns::new(0);
+    let code = compile_modules(modules, &mut interns, (library::Standard, natives), Options::default())?;
+    Ok((interns, code))
+}
+
+pub fn run(src: &str, entry: &str) -> String {
+    run_modules(&[src], entry)
+}
+
+// `entry` takes no args, and a compile error comes back as "compile error: ..."
+pub fn run_modules(srcs: &[&str], entry: &str) -> String {
+    let (interns, code) = match compile(srcs) {
+        Ok(compiled) => compiled,
+        Err(e) => { return format!("compile error: {:?}", e); }
+    };
+    call(&interns, &code, entry)
+}
+
+pub fn call(interns: &Interns, code: &Executable1, entry: &str) -> String {
+    call_with(interns, code, &mut (), entry)
+}
+
+pub fn call_with<H>(interns: &Interns, code: &Executable1<H>, host: &mut H, entry: &str) -> String {
+    let call = Value::compound(interns.to_intern(entry).expect("no such procedure"), vec![]);
+    let mut vm = match VM::go(interns, code, host, call) {
+        Ok(vm) => vm,
+        Err(e) => { return format!("failed: {:?}", e); }
+    };
+    while vm.is_running() {
+        vm.run(interns, host, usize::MAX);
+    }
+    finished(interns, vm)
+}
+
+pub fn finished<H>(interns: &Interns, vm: VM<H>) -> String {
+    match vm {
+        VM::Succeeded(value, _) => show(interns, &value),
+        VM::Failed(e) => format!("failed: {:?}", e),
+        VM::Yielded(_, value) => format!("yielded: {}", show(interns, &value)),
+        VM::Suspended(_) => "suspended".to_owned(),
+        VM::Waiting(_) => "waiting".to_owned(),
+        VM::Running(_) => "running".to_owned(),
+    }
+}
+
+pub fn show(interns: &Interns, value: &Value) -> String {
+    let mut shown = String::new();
+    library::show(interns, value, &mut shown);
+    shown
+}
@@ -0,0 +1,652 @@
+use crate::irs::ast1;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::errors::compiler::*;
+
+use super::{Signature, Type, Types};
+use super::unify::{Mismatch, Substitution};
+
+use ast1::{BinOp, Block, Condition, Expression, Module, Pattern, Procedure, Span, Statement, TypeDecl, TypeExpr};
+
+type Key = (String, usize);
+
+// A signature with its free type variables quantified, so every call gets fresh copies
+struct Scheme {
+    vars: Vec<usize>,
+    args: Vec<Type>,
+    ret: Type,
+}
+
+// Every module, each after the ones it imports, so a call into another module gets the
+// callee's inferred type. Calls that can't be inferred that way are `Dyn`: into a module
+// that imports this one (the root module, say), or around an import cycle
+pub fn infer_modules(modules: &[Module], types: &[TypeDecl]) -> Compiler<Types> {
+    let mut all = Types::default();
+    let mut left: Vec<&Module> = modules.iter().collect();
+    while !left.is_empty() {
+        let pending: HashSet<&String> = left.iter().filter_map(|m| m.name.as_ref()).collect();
+        let next = left.iter()
+            .position(|m| m.imports.iter().all(|i| !pending.contains(i) || m.name.as_ref() == Some(i)))
+            .unwrap_or(0);
+        let module = left.remove(next);
+        let inferred = module.infer_types_with(types, &all)?;
+        all.add_module(&module.name, inferred);
+    }
+    Ok(all)
+}
+
+impl Module {
+    // Hindley-Milner, more or less: procedures are generalized one strongly-connected
+    // component of the call graph at a time, so mutually recursive procedures are
+    // monomorphic in each other. Values whose types aren't known statically are `Dyn`.
+    // Calls to anything but this module's own procedures are `Dyn`.
+    pub fn infer_types(&self) -> Compiler<Types> {
+        self.infer_types_with(&self.types, &Types::default())
+    }
+
+    // with `types` as the declarations in scope, when they come from every module,
+    // and `imported` as what's known of the procedures outside this one, by qualified name
+    pub fn infer_types_with(&self, types: &[TypeDecl], imported: &Types) -> Compiler<Types> {
+        let mut keys: Vec<Key> = vec![];
+        let mut by_key: HashMap<Key, Vec<&Procedure>> = HashMap::new();
+        for p in self.procedures.iter() {
+            let key = (p.name.clone(), p.args.len());
+            if !by_key.contains_key(&key) { keys.push(key.clone()); }
+            by_key.entry(key).or_insert_with(|| vec![]).push(p);
+        }
+
+        let mut inferrer = Inferrer {
+            module: self.name.clone(),
+            subst: Substitution::new(),
+            constructors: constructors(types),
+            consts: HashMap::new(),
+            globals: HashMap::new(),
+            schemes: HashMap::new(),
+            mono: HashMap::new(),
+            span: Span(0, 0),
+            locals: HashMap::new(),
+            ret: Type::Dyn,
+        };
+        let mut signatures = HashMap::new();
+
+        // a procedure here hides one of the same name elsewhere, as it does when compiled
+        for (key, signature) in imported.signatures() {
+            if by_key.contains_key(key) { continue; }
+            let mut vars = vec![];
+            for t in signature.args.iter().chain(Some(&signature.ret)) { free_vars(t, &mut vars); }
+            let scheme = Scheme { vars, args: signature.args.clone(), ret: signature.ret.clone() };
+            inferrer.schemes.insert(key.clone(), scheme);
+        }
+
+        // a const that uses a later one just sees it as `any`
+        for c in self.consts.iter() {
+            let t = inferrer.constant(&c.value)?;
+            inferrer.consts.insert(c.name.clone(), t);
+        }
+        // a global holds whatever is put there, which has to be the same type as where it starts
+        for g in self.globals.iter() {
+            let t = inferrer.constant(&g.value)?;
+            inferrer.globals.insert(g.name.clone(), t);
+        }
+
+        for component in components(&self.name, &keys, &by_key) {
+            for key in component.iter() {
+                let args = (0..key.1).map(|_| inferrer.subst.fresh()).collect();
+                let ret = inferrer.subst.fresh();
+                inferrer.mono.insert(key.clone(), (args, ret));
+            }
+
+            let mut locals = vec![];
+            for key in component.iter() {
+                let mut merged = HashMap::new();
+                for p in by_key[key].iter() {
+                    merged.extend(inferrer.procedure(p, key)?);
+                }
+                locals.push(merged);
+            }
+
+            for (key, locals) in component.into_iter().zip(locals) {
+                let (args, ret) = inferrer.mono.remove(&key).unwrap();
+                let (scheme, signature) = inferrer.generalize(args, ret, locals);
+                inferrer.schemes.insert(key.clone(), scheme);
+                signatures.insert(key, signature);
+            }
+        }
+
+        Ok(Types { signatures })
+    }
+}
+
+fn constructors(types: &[TypeDecl]) -> HashMap<String, (String, Vec<Type>)> {
+    fn convert(t: &TypeExpr) -> Type {
+        match t {
+            TypeExpr::Named(n) if n == "int" => Type::Int,
+            TypeExpr::Named(n) if n == "bool" => Type::Bool,
+            TypeExpr::Named(n) if n == "any" => Type::Dyn,
+            TypeExpr::Named(n) => Type::Named(n.clone()),
+            TypeExpr::Vector(box t) => Type::Vector(box convert(t)),
+            TypeExpr::Set(box t) => Type::Set(box convert(t)),
+        }
+    }
+
+    let mut constructors = HashMap::new();
+    for t in types {
+        for v in t.variants.iter() {
+            constructors.insert(v.name.clone(), (t.name.clone(), v.args.iter().map(convert).collect()));
+        }
+    }
+    constructors
+}
+
+struct Inferrer {
+    module: Option<String>,
+    subst: Substitution,
+    constructors: HashMap<String, (String, Vec<Type>)>,
+    consts: HashMap<String, Type>,
+    globals: HashMap<String, Type>,
+
+    schemes: HashMap<Key, Scheme>, // finished procedures
+    mono: HashMap<Key, (Vec<Type>, Type)>, // procedures in the component being inferred
+
+    // per procedure
+    span: Span,
+    locals: HashMap<String, Type>,
+    ret: Type,
+}
+
+impl Inferrer {
+    // the type of a const's value, where anything left open is `any`
+    fn constant(&mut self, xp: &Expression) -> Compiler<Type> {
+        let t = self.expression(xp)?;
+        let mut vars = vec![];
+        let t = self.subst.resolve(&t);
+        free_vars(&t, &mut vars);
+        Ok(substitute(&t, &vars.into_iter().map(|v| (v, Type::Dyn)).collect()))
+    }
+
+    // the type of what's under a key in the store, if the key is a global's name
+    fn global(&self, key: &Expression) -> Option<Type> {
+        match key {
+            Expression::Compound(name, args) if args.is_empty() => self.globals.get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    fn procedure(&mut self, p: &Procedure, key: &Key) -> Compiler<HashMap<String, Type>> {
+        let (args, ret) = self.mono[key].clone();
+        self.locals = HashMap::new();
+        self.ret = ret;
+        self.span = p.span;
+
+        for (pat, t) in p.args.iter().zip(args.iter()) {
+            let found = self.pattern(pat)?;
+            self.unify(t, &found)?;
+        }
+        self.block(&p.body)?;
+
+        Ok(std::mem::replace(&mut self.locals, HashMap::new()))
+    }
+
+    fn block(&mut self, bl: &Block) -> Compiler<()> {
+        let outer = self.span;
+        for (span, st) in bl.0.iter() {
+            self.span = *span;
+            self.statement(st)?;
+        }
+        self.span = outer;
+        Ok(())
+    }
+
+    fn statement(&mut self, st: &Statement) -> Compiler<()> {
+        match st {
+            Statement::If(cond, bl_then, obl_else) => {
+                match cond {
+                    Condition::Let(lhs, rhs) => {
+                        let expected = self.expression(rhs)?;
+                        let found = self.pattern(lhs)?;
+                        self.unify(&expected, &found)?;
+                    }
+                    Condition::Bare(xp) => {
+                        let found = self.expression(xp)?;
+                        self.unify(&Type::Bool, &found)?;
+                    }
+                }
+                self.block(bl_then)?;
+                if let Some(bl_else) = obl_else { self.block(bl_else)?; }
+            }
+            Statement::Assign(lhs, rhs) => {
+                let found = self.expression(rhs)?;
+                let expected = self.local(lhs);
+                self.unify(&expected, &found)?;
+            }
+            Statement::VecPush(lhs, rhs) => {
+                let found = self.expression(rhs)?;
+                let expected = self.local(lhs);
+                self.unify(&expected, &Type::Vector(box found))?;
+            }
+            Statement::VecSet(lhs, index, rhs) => {
+                let found = self.expression(index)?;
+                self.unify(&Type::Int, &found)?;
+                let found = self.expression(rhs)?;
+                let expected = self.local(lhs);
+                self.unify(&expected, &Type::Vector(box found))?;
+            }
+            Statement::SetArg(_, _, rhs) => {
+                // which argument that is depends on which variant the local holds
+                self.expression(rhs)?;
+            }
+            Statement::Destructure(lhs, rhs) => {
+                let expected = self.expression(rhs)?;
+                let found = self.pattern(lhs)?;
+                self.unify(&expected, &found)?;
+            }
+            Statement::Match(scrutinee, arms) => {
+                let expected = self.expression(scrutinee)?;
+                for (pat, bl) in arms.iter() {
+                    let found = self.pattern(pat)?;
+                    self.unify(&expected, &found)?;
+                    self.block(bl)?;
+                }
+            }
+            Statement::Send(pid, msg) => {
+                self.expression(pid)?;
+                self.expression(msg)?;
+            }
+            Statement::Receive(arms) => {
+                // messages can be anything
+                for (pat, bl) in arms.iter() {
+                    self.pattern(pat)?;
+                    self.block(bl)?;
+                }
+            }
+            Statement::Atomic(bl) => { self.block(bl)?; }
+            Statement::Abort => {}
+            Statement::StorePut(key, value) => {
+                let found = self.expression(value)?;
+                match self.global(key) {
+                    Some(expected) => self.unify(&expected, &found)?,
+                    None => { self.expression(key)?; }
+                }
+            }
+            Statement::Eval(xp) => { self.expression(xp)?; }
+            Statement::Ret(xp) => {
+                let found = self.expression(xp)?;
+                let expected = self.ret.clone();
+                self.unify(&expected, &found)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, xp: &Expression) -> Compiler<Type> {
+        Ok(match xp {
+            Expression::NoOp => Type::Dyn,
+            Expression::IntLiteral(_) => Type::Int,
+            Expression::Variable(n) => self.local(n),
+            Expression::VecPop(n) => {
+                let element = self.subst.fresh();
+                let found = self.local(n);
+                self.unify(&Type::Vector(box element.clone()), &found)?;
+                element
+            }
+            Expression::Call(box Expression::Compound(name, args)) => {
+                let mut found = vec![];
+                for arg in args { found.push(self.expression(arg)?); }
+
+                let key = (own_name(&self.module, name).to_owned(), args.len());
+                let (expected, ret) = match self.mono.get(&key) {
+                    Some((expected, ret)) => (expected.clone(), ret.clone()),
+                    None => match self.schemes.get(&key) {
+                        Some(_) => self.instantiate(&key),
+                        None => { return Ok(Type::Dyn); } // a native, or nothing at all
+                    }
+                };
+                for (e, f) in expected.iter().zip(found.iter()) {
+                    self.unify(e, f)?;
+                }
+                ret
+            }
+            Expression::Call(box e) => {
+                self.expression(e)?;
+                Type::Dyn
+            }
+            Expression::Spawn(box e) => {
+                self.expression(e)?;
+                Type::Dyn
+            }
+            Expression::SelfPid => Type::Dyn,
+            Expression::Yield(box e) => {
+                // the host can answer with anything
+                self.expression(e)?;
+                Type::Dyn
+            }
+            Expression::StoreGet(box key) => match self.global(key) {
+                Some(t) => t,
+                None => {
+                    // anything could have been put there
+                    self.expression(key)?;
+                    Type::Dyn
+                }
+            },
+            Expression::Apply(box e, args) => {
+                self.expression(e)?;
+                for arg in args { self.expression(arg)?; }
+                Type::Term
+            }
+            Expression::Lambda(args, body) => {
+                // Captured locals are shared with the enclosing procedure, but the return isn't,
+                // and neither are the lambda's args or anything it binds that wasn't bound out here
+                let mut own = vec![];
+                for arg in args { pattern_vars(arg, &mut own); }
+                let mut inner = self.locals.clone();
+                for n in own.iter() { inner.remove(n); }
+                let outer_locals = std::mem::replace(&mut self.locals, inner);
+
+                let ret = self.subst.fresh();
+                let outer_ret = std::mem::replace(&mut self.ret, ret);
+                for arg in args { self.pattern(arg)?; }
+                self.block(body)?;
+                self.ret = outer_ret;
+                self.locals = outer_locals;
+                Type::Term
+            }
+            Expression::Compound(name, args) if args.is_empty() && self.consts.contains_key(name) => {
+                self.consts[name].clone()
+            }
+            Expression::Compound(name, args) => {
+                let mut found = vec![];
+                for arg in args { found.push(self.expression(arg)?); }
+                self.constructor(name, &found)?
+            }
+            Expression::Vector(args) => {
+                let element = self.subst.fresh();
+                for arg in args {
+                    let found = self.expression(arg)?;
+                    self.unify(&element, &found)?;
+                }
+                Type::Vector(box element)
+            }
+            Expression::Set(args) => {
+                let element = self.subst.fresh();
+                for arg in args {
+                    let found = self.expression(arg)?;
+                    self.unify(&element, &found)?;
+                }
+                Type::Set(box element)
+            }
+            Expression::Binary(box lhs, op, box rhs) => {
+                let (operand, result) = match op {
+                    BinOp::And | BinOp::Or => (Type::Bool, Type::Bool),
+                    BinOp::Multiply | BinOp::Divide | BinOp::Add | BinOp::Subtract => (Type::Int, Type::Int),
+                    BinOp::Le | BinOp::Ge | BinOp::Lt | BinOp::Gt | BinOp::Eq | BinOp::Ne => (Type::Int, Type::Bool),
+                };
+                let found = self.expression(lhs)?;
+                self.unify(&operand, &found)?;
+                let found = self.expression(rhs)?;
+                self.unify(&operand, &found)?;
+                result
+            }
+        })
+    }
+
+    fn pattern(&mut self, pat: &Pattern) -> Compiler<Type> {
+        Ok(match pat {
+            Pattern::IntLiteral(_) => Type::Int,
+            Pattern::Variable(n) => self.local(n),
+            Pattern::Compound(name, args) if args.is_empty() && self.consts.contains_key(name) => {
+                self.consts[name].clone()
+            }
+            Pattern::Compound(name, args) => {
+                let mut found = vec![];
+                for arg in args { found.push(self.pattern(arg)?); }
+                self.constructor(name, &found)?
+            }
+            Pattern::WcCompound(args) => {
+                for arg in args { self.pattern(arg)?; }
+                Type::Dyn
+            }
+            Pattern::Vector(args) => {
+                let element = self.subst.fresh();
+                for arg in args {
+                    let found = self.pattern(arg)?;
+                    self.unify(&element, &found)?;
+                }
+                Type::Vector(box element)
+            }
+        })
+    }
+
+    fn constructor(&mut self, name: &str, found: &[Type]) -> Compiler<Type> {
+        let (t, expected) = match self.constructors.get(name) {
+            Some((t, expected)) if expected.len() == found.len() => (t.clone(), expected.clone()),
+            _ => { return Ok(Type::Term); }
+        };
+        for (e, f) in expected.iter().zip(found.iter()) {
+            self.unify(e, f)?;
+        }
+        Ok(Type::Named(t))
+    }
+
+    fn local(&mut self, name: &str) -> Type {
+        if let Some(t) = self.locals.get(name) { return t.clone(); }
+        let t = self.subst.fresh();
+        self.locals.insert(name.to_owned(), t.clone());
+        t
+    }
+
+    fn unify(&mut self, expected: &Type, found: &Type) -> Compiler<()> {
+        match self.subst.unify(expected, found) {
+            Ok(()) => Ok(()),
+            Err(Mismatch::Different(e, f)) => Err(Error::TypeMismatch(self.span, e, f)),
+            Err(Mismatch::Infinite) => Err(Error::InfiniteType(self.span)),
+        }
+    }
+
+    fn instantiate(&mut self, key: &Key) -> (Vec<Type>, Type) {
+        let scheme = &self.schemes[key];
+        let mut fresh = HashMap::new();
+        for v in scheme.vars.iter() {
+            fresh.insert(*v, self.subst.fresh());
+        }
+        let scheme = &self.schemes[key];
+        (
+            scheme.args.iter().map(|t| substitute(t, &fresh)).collect(),
+            substitute(&scheme.ret, &fresh),
+        )
+    }
+
+    fn generalize(&mut self, args: Vec<Type>, ret: Type, locals: HashMap<String, Type>) -> (Scheme, Signature) {
+        let args: Vec<Type> = args.iter().map(|t| self.subst.resolve(t)).collect();
+        let ret = self.subst.resolve(&ret);
+        let locals: BTreeMap<String, Type> = locals.into_iter()
+            .map(|(n, t)| (n, self.subst.resolve(&t)))
+            .collect();
+
+        // nothing is in scope at the top level, so every free variable gets quantified
+        let mut vars = vec![];
+        for t in args.iter().chain(Some(&ret)) { free_vars(t, &mut vars); }
+
+        // renumber from 'a for display: signature vars first, then anything local-only
+        let mut display_order = vars.clone();
+        for t in locals.values() { free_vars(t, &mut display_order); }
+        let renumber: HashMap<usize, Type> = display_order.iter().enumerate()
+            .map(|(i, v)| (*v, Type::Var(i)))
+            .collect();
+
+        let signature = Signature {
+            args: args.iter().map(|t| substitute(t, &renumber)).collect(),
+            ret: substitute(&ret, &renumber),
+            locals: locals.iter().map(|(n, t)| (n.clone(), substitute(t, &renumber))).collect(),
+        };
+        (Scheme { vars, args, ret }, signature)
+    }
+}
+
+// a call to `module::name` from inside `module` is to `name`
+fn own_name<'n>(module: &Option<String>, name: &'n str) -> &'n str {
+    match module {
+        Some(m) if name.len() > m.len() + 2 && name.starts_with(m.as_str()) && name[m.len()..].starts_with("::") => {
+            &name[m.len() + 2..]
+        }
+        _ => name,
+    }
+}
+
+fn free_vars(t: &Type, into: &mut Vec<usize>) {
+    match t {
+        Type::Var(v) => { if !into.contains(v) { into.push(*v); } }
+        Type::Vector(box t) => free_vars(t, into),
+        Type::Set(box t) => free_vars(t, into),
+        _ => {}
+    }
+}
+
+fn substitute(t: &Type, with: &HashMap<usize, Type>) -> Type {
+    match t {
+        Type::Var(v) => with.get(v).cloned().unwrap_or(Type::Var(*v)),
+        Type::Vector(box t) => Type::Vector(box substitute(t, with)),
+        Type::Set(box t) => Type::Set(box substitute(t, with)),
+        t => t.clone(),
+    }
+}
+
+// Tarjan's algorithm. Components come out callees-first, which is the order we want to infer them in.
+fn components(module: &Option<String>, keys: &[Key], by_key: &HashMap<Key, Vec<&Procedure>>) -> Vec<Vec<Key>> {
+    struct State<'k> {
+        edges: HashMap<&'k Key, Vec<Key>>,
+        index: HashMap<Key, usize>,
+        lowlink: HashMap<Key, usize>,
+        stack: Vec<Key>,
+        components: Vec<Vec<Key>>,
+    }
+
+    fn visit(st: &mut State, key: &Key) {
+        let ix = st.index.len();
+        st.index.insert(key.clone(), ix);
+        st.lowlink.insert(key.clone(), ix);
+        st.stack.push(key.clone());
+
+        for callee in st.edges[key].clone() {
+            if !st.index.contains_key(&callee) {
+                visit(st, &callee);
+                let low = st.lowlink[key].min(st.lowlink[&callee]);
+                st.lowlink.insert(key.clone(), low);
+            } else if st.stack.contains(&callee) {
+                let low = st.lowlink[key].min(st.index[&callee]);
+                st.lowlink.insert(key.clone(), low);
+            }
+        }
+
+        if st.lowlink[key] == st.index[key] {
+            let mut component = vec![];
+            loop {
+                let k = st.stack.pop().unwrap();
+                let done = &k == key;
+                component.push(k);
+                if done { break; }
+            }
+            component.reverse();
+            st.components.push(component);
+        }
+    }
+
+    let mut st = State {
+        edges: HashMap::new(),
+        index: HashMap::new(),
+        lowlink: HashMap::new(),
+        stack: vec![],
+        components: vec![],
+    };
+    for key in keys {
+        let mut callees = vec![];
+        for p in by_key[key].iter() {
+            for (_, s) in p.body.0.iter() { calls_in_statement(s, &mut callees); }
+        }
+        for callee in callees.iter_mut() { callee.0 = own_name(module, &callee.0).to_owned(); }
+        callees.retain(|c| by_key.contains_key(c));
+        st.edges.insert(key, callees);
+    }
+    for key in keys {
+        if !st.index.contains_key(key) { visit(&mut st, key); }
+    }
+    st.components
+}
+
+fn calls_in_statement(st: &Statement, into: &mut Vec<Key>) {
+    let block = |bl: &Block, into: &mut Vec<Key>| {
+        for (_, s) in bl.0.iter() { calls_in_statement(s, into); }
+    };
+    match st {
+        Statement::If(cond, bl_then, obl_else) => {
+            match cond {
+                Condition::Let(_, xp) => calls_in_expression(xp, into),
+                Condition::Bare(xp) => calls_in_expression(xp, into),
+            }
+            block(bl_then, into);
+            if let Some(bl_else) = obl_else { block(bl_else, into); }
+        }
+        Statement::Assign(_, xp) => calls_in_expression(xp, into),
+        Statement::VecPush(_, xp) => calls_in_expression(xp, into),
+        Statement::VecSet(_, ix, xp) => {
+            calls_in_expression(ix, into);
+            calls_in_expression(xp, into);
+        }
+        Statement::SetArg(_, _, xp) => calls_in_expression(xp, into),
+        Statement::Destructure(_, xp) => calls_in_expression(xp, into),
+        Statement::Match(xp, arms) => {
+            calls_in_expression(xp, into);
+            for (_, bl) in arms.iter() { block(bl, into); }
+        }
+        Statement::Send(pid, msg) => {
+            calls_in_expression(pid, into);
+            calls_in_expression(msg, into);
+        }
+        Statement::Receive(arms) => for (_, bl) in arms.iter() { block(bl, into); },
+        Statement::Atomic(bl) => block(bl, into),
+        Statement::Abort => {}
+        Statement::StorePut(key, value) => {
+            calls_in_expression(key, into);
+            calls_in_expression(value, into);
+        }
+        Statement::Eval(xp) => calls_in_expression(xp, into),
+        Statement::Ret(xp) => calls_in_expression(xp, into),
+    }
+}
+
+fn calls_in_expression(xp: &Expression, into: &mut Vec<Key>) {
+    match xp {
+        Expression::Call(box Expression::Compound(name, args)) => {
+            into.push((name.clone(), args.len()));
+            for arg in args { calls_in_expression(arg, into); }
+        }
+        Expression::Call(box e) | Expression::Spawn(box e) | Expression::Yield(box e) |
+        Expression::StoreGet(box e) => calls_in_expression(e, into),
+        Expression::Apply(box e, args) => {
+            calls_in_expression(e, into);
+            for arg in args { calls_in_expression(arg, into); }
+        }
+        Expression::Lambda(_, body) => {
+            for (_, s) in body.0.iter() { calls_in_statement(s, into); }
+        }
+        Expression::Compound(_, args) => for arg in args { calls_in_expression(arg, into); },
+        Expression::Vector(args) => for arg in args { calls_in_expression(arg, into); },
+        Expression::Set(args) => for arg in args { calls_in_expression(arg, into); },
+        Expression::Binary(box lhs, _, box rhs) => {
+            calls_in_expression(lhs, into);
+            calls_in_expression(rhs, into);
+        }
+        Expression::NoOp | Expression::IntLiteral(_) | Expression::Variable(_) | Expression::VecPop(_) |
+        Expression::SelfPid => {}
+    }
+}
+
+fn pattern_vars(pat: &Pattern, into: &mut Vec<String>) {
+    match pat {
+        Pattern::IntLiteral(_) => {}
+        Pattern::Variable(n) => into.push(n.clone()),
+        Pattern::Compound(_, args) | Pattern::WcCompound(args) | Pattern::Vector(args) => {
+            for arg in args { pattern_vars(arg, into); }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+mod infer;
+mod unify;
+
+pub use infer::infer_modules;
+
+use crate::irs::procedure1::qualify;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+// Inferred types. Nothing here is used by the code generator: the pass only runs when
+// `Options::check_types` asks for it, and then a module that fails it doesn't compile,
+// and one that passes has its types in `Executable1::types`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Type {
+    Var(usize),
+    Dyn, // not known until runtime (results of natives and calls through variables)
+
+    Int,
+    Bool,
+    Term, // a compound with an undeclared functor
+    Named(String), // a declared type
+
+    Vector(Box<Type>),
+    Set(Box<Type>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Signature {
+    pub args: Vec<Type>,
+    pub ret: Type,
+    pub locals: BTreeMap<String, Type>,
+}
+
+#[derive(Debug, Default)]
+pub struct Types {
+    signatures: HashMap<(String, usize), Signature>,
+}
+
+impl Types {
+    pub fn signature(&self, name: &str, arity: usize) -> Option<&Signature> {
+        self.signatures.get(&(name.to_owned(), arity))
+    }
+
+    pub fn signatures(&self) -> impl Iterator<Item=(&(String, usize), &Signature)> {
+        self.signatures.iter()
+    }
+
+    // one module's, under the names other modules call them by
+    fn add_module(&mut self, module: &Option<String>, types: Types) {
+        for ((name, arity), signature) in types.signatures {
+            self.signatures.insert((qualify(module, &name), arity), signature);
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Var(v) => {
+                // 'a .. 'z, then 'a1 ..
+                let letter = (b'a' + (v % 26) as u8) as char;
+                if *v < 26 { write!(f, "'{}", letter) } else { write!(f, "'{}{}", letter, v / 26) }
+            }
+            Type::Dyn => write!(f, "?"),
+            Type::Int => write!(f, "int"),
+            Type::Bool => write!(f, "bool"),
+            Type::Term => write!(f, "term"),
+            Type::Named(n) => write!(f, "{}", n),
+            Type::Vector(t) => write!(f, "v[{}]", t),
+            Type::Set(t) => write!(f, "s[{}]", t),
+        }
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 { write!(f, ", ")?; }
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ") -> {}", self.ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Options;
+    use crate::errors::compiler::Error;
+    use crate::parser::parse_module;
+    use crate::testing::compile_with;
+
+    // every signature, one per line, or where inference failed
+    fn infer(src: &str) -> String {
+        match parse_module(src).unwrap().infer_types() {
+            Ok(types) => {
+                let mut lines: Vec<String> = types.signatures()
+                    .map(|((name, arity), signature)| format!("{}/{}: {}", name, arity, signature))
+                    .collect();
+                lines.sort();
+                lines.join("\n")
+            }
+            Err(Error::TypeMismatch(span, expected, found)) => {
+                format!("at {:?}: expected {}, found {}", span.locate(src), expected, found)
+            }
+            Err(Error::InfiniteType(span)) => format!("infinite at {:?}", span.locate(src)),
+            Err(e) => format!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn procedures() {
+        let src = "
+            type shape = circle(int) | rect(int, int).
+            fn area(@s) {
+                match @s {
+                    circle(@r) => { ret 3 * @r * @r. }
+                    rect(@w, @h) => { ret @w * @h. }
+                }
+            }
+            fn id(@x) { ret @x. }
+            fn even(@n) { if @n == 0 { ret 1 < 2. } ret call odd(@n - 1). }
+            fn odd(@n) { if @n == 0 { ret 2 < 1. } ret call even(@n - 1). }
+            fn main { ret v[call area(call id(circle(2))), call id(1)]. }
+        ";
+        assert_eq!(infer(src), "\
+area/1: (shape) -> int
+even/1: (int) -> bool
+id/1: ('a) -> 'a
+main/0: () -> v[int]
+odd/1: (int) -> bool");
+    }
+
+    #[test]
+    fn vectors_and_sets() {
+        let src = "
+            fn first(v[@a, @b]) { ret @a. }
+            fn wrap(@x) { ret s[@x]. }
+            fn grow(@v, @x) { push @v, @x. ret @v. }
+            fn set(@v) { now @v[0] = 1 < 2. ret @v. }
+            fn pop(@v) { ret pop @v + 1. }
+            fn nested { ret v[call wrap(1), s[]]. }
+        ";
+        assert_eq!(infer(src), "\
+first/1: (v['a]) -> 'a
+grow/2: (v['a], 'a) -> v['a]
+nested/0: () -> v[s[int]]
+pop/1: (v[int]) -> int
+set/1: (v[bool]) -> v[bool]
+wrap/1: ('a) -> s['a]");
+    }
+
+    #[test]
+    fn mismatches() {
+        assert_eq!(infer("fn f {\n  let @x = 1.\n  ret @x + v[]. }"), "at (3, 3): expected int, found v['c]");
+        assert_eq!(infer("fn f { ret v[1, true]. }"), "at (1, 8): expected int, found term");
+        assert_eq!(infer("fn f { ret s[v[1], v[s[]]]. }"), "at (1, 8): expected int, found s['e]");
+        assert_eq!(infer("fn f(@v) { push @v, 1. push @v, 1 < 2. ret @v. }"), "at (1, 24): expected int, found bool");
+        assert_eq!(infer("type t = a(int). fn f { ret a(yes). }"), "at (1, 25): expected int, found term");
+        assert_eq!(infer("fn f(@v) { push @v, @v. ret @v. }"), "infinite at (1, 12)");
+    }
+
+    #[test]
+    fn across_modules() {
+        let geometry = "module geometry. pub fn double(@x) { ret @x + @x. } pub fn pair(@x) { ret v[@x, @x]. }";
+        let main = "import geometry. pub fn main { ret call geometry::pair(call geometry::double(2)). }";
+        let options = Options { check_types: true, ..Options::default() };
+        // whichever order they come in
+        for srcs in &[[main, geometry], [geometry, main]] {
+            let (_, code) = compile_with(srcs, options).unwrap();
+            let types = code.types.unwrap();
+            assert_eq!(types.signature("geometry::double", 1).unwrap().to_string(), "(int) -> int");
+            assert_eq!(types.signature("main", 0).unwrap().to_string(), "() -> v[int]");
+        }
+        let bad = "import geometry. pub fn main { ret call geometry::double(v[]). }";
+        match compile_with(&[bad, geometry], options) {
+            Err(Error::TypeMismatch(..)) => {}
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        // not asked for
+        assert!(compile_with(&[main, geometry], Options::default()).unwrap().1.types.is_none());
+    }
+}
@@ -0,0 +1,75 @@
+use super::Type;
+
+// Union-find in its laziest form: a var is either unbound or bound to a type,
+// which may be another var.
+#[derive(Debug)]
+pub struct Substitution {
+    bindings: Vec<Option<Type>>,
+}
+
+pub enum Mismatch {
+    Different(Type, Type),
+    Infinite,
+}
+
+impl Substitution {
+    pub fn new() -> Substitution {
+        Substitution { bindings: vec![] }
+    }
+
+    pub fn fresh(&mut self) -> Type {
+        self.bindings.push(None);
+        Type::Var(self.bindings.len() - 1)
+    }
+
+    // resolve the outermost constructor only
+    fn shallow(&self, t: &Type) -> Type {
+        let mut t = t.clone();
+        while let Type::Var(v) = t {
+            match &self.bindings[v] {
+                Some(bound) => { t = bound.clone(); }
+                None => { break; }
+            }
+        }
+        t
+    }
+
+    pub fn resolve(&self, t: &Type) -> Type {
+        match self.shallow(t) {
+            Type::Vector(box t) => Type::Vector(box self.resolve(&t)),
+            Type::Set(box t) => Type::Set(box self.resolve(&t)),
+            t => t,
+        }
+    }
+
+    pub fn unify(&mut self, t1: &Type, t2: &Type) -> Result<(), Mismatch> {
+        match (self.shallow(t1), self.shallow(t2)) {
+            (Type::Var(v1), Type::Var(v2)) if v1 == v2 => Ok(()),
+            (Type::Var(v), t) | (t, Type::Var(v)) => {
+                if self.occurs(v, &t) { return Err(Mismatch::Infinite); }
+                self.bindings[v] = Some(t);
+                Ok(())
+            }
+            // gradual: anything dynamic is checked at runtime instead
+            (Type::Dyn, _) | (_, Type::Dyn) => Ok(()),
+
+            (Type::Int, Type::Int) => Ok(()),
+            (Type::Bool, Type::Bool) => Ok(()),
+            (Type::Term, Type::Term) => Ok(()),
+            (Type::Named(n1), Type::Named(n2)) if n1 == n2 => Ok(()),
+            (Type::Vector(box e1), Type::Vector(box e2)) => self.unify(&e1, &e2),
+            (Type::Set(box e1), Type::Set(box e2)) => self.unify(&e1, &e2),
+
+            (s1, s2) => Err(Mismatch::Different(self.resolve(&s1), self.resolve(&s2))),
+        }
+    }
+
+    fn occurs(&self, v: usize, t: &Type) -> bool {
+        match self.shallow(t) {
+            Type::Var(v2) => v == v2,
+            Type::Vector(box t) => self.occurs(v, &t),
+            Type::Set(box t) => self.occurs(v, &t),
+            _ => false,
+        }
+    }
+}
@@ -1,63 +1,184 @@
 mod runner;
-mod stackframe;
+mod stack;
 
 use crate::errors::runtime::*;
 use crate::interns::Interns;
 use crate::irs::executable1::Executable1;
 use crate::irs::procedure2::Procedure2;
-use crate::primitive::Local;
+use crate::primitive::{Local, Pid};
 use crate::primitive::Value;
+use crate::scheduler::PostOffice;
+use crate::store::Store;
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::mem;
+use std::rc::Rc;
 
 use runner::Runner;
-use stackframe::StackFrame;
 
 
 #[derive(Debug)]
-pub enum VM<'a> {
-    Updating,
-    Running(Runner<'a>),
+pub enum VM<'a, H = ()> {
+    Running(Runner<'a, H>),
+    Suspended(Runner<'a, H>), // out of fuel: `refuel` picks it back up where it stopped
+    Waiting(Runner<'a, H>), // on a receive with nothing new in the mailbox: `wake` tries again
+    Yielded(Runner<'a, H>, Value), // asking the host for something: `resume` gives the answer
     Succeeded(Value, Vec<Option<Value>>), // keep the vars from the frame, to extract in the repl
     Failed(Error),
 }
 
-impl<'a> VM<'a> {
+// why the runner handed control back before its budget ran out
+#[derive(Debug)]
+pub enum Stop {
+    Succeeded(Value, Vec<Option<Value>>),
+    OutOfFuel,
+    Waiting,
+    Yielded(Value),
+}
+
+// Anything that can run code takes the host's context, to hand to natives.
+impl<'a, H> VM<'a, H> {
     pub fn start_repl(
         repl_proc: &'a Procedure2,
-        executable: &'a Executable1,
+        executable: &'a Executable1<H>,
         var_alloc: &HashMap<String, Local>,
         var_value: &mut HashMap<String, Value>,
     ) -> Self {
         let mut runner = Runner {
             c: executable,
-            f: vec![crate::vm::StackFrame::new_on(&repl_proc)],
+            f: vec![],
+            s: vec![],
+            natives: vec![],
+            outside: 1,
+            fuel: u64::MAX,
+            post: None,
+            store: None,
+            writes: BTreeMap::new(),
+            atomics: vec![],
         };
+        runner.enter(repl_proc);
         for (k, v) in var_alloc.iter() {
-            runner.f[0].v[v.0] = var_value.remove(k);
+            runner.s[v.0] = var_value.remove(k);
         } ;
         VM::Running(runner)
     }
 
-    pub fn go<'proto>(interns: &Interns<'proto>, code: &'a Executable1, call: Value) -> Runtime<Self> {
-        Runner::new(code).call(interns, call)
+    pub fn go<'proto>(interns: &Interns<'proto>, code: &'a Executable1<H>, host: &mut H, call: Value) -> Runtime<Self> {
+        let mut runner = Runner::new(code);
+        let stopped = runner.call(interns, host, call)?;
+        let mut vm = VM::Running(runner);
+        vm.settle(Ok(stopped));
+        Ok(vm)
+    }
+
+    // like `go`, for a call that came from a `spawn` in the program
+    pub fn go_spawned<'proto>(interns: &Interns<'proto>, code: &'a Executable1<H>, host: &mut H, call: Value) -> Runtime<Self> {
+        let mut runner = Runner::new(code);
+        let stopped = runner.call_spawned(interns, host, call)?;
+        let mut vm = VM::Running(runner);
+        vm.settle(Ok(stopped));
+        Ok(vm)
+    }
+
+    // Exactly one instruction, for stepping through a program.
+    pub fn update<'proto>(&mut self, interns: &Interns<'proto>, host: &mut H) {
+        if let VM::Running(runner) = self {
+            let stopped = runner.update(interns, host);
+            self.settle(stopped);
+        }
     }
 
-    pub fn update<'proto>(&mut self, interns: &Interns<'proto>) {
-        let mut tar = VM::Updating;
-        mem::swap(self, &mut tar);
+    // Up to `budget` instructions without coming back out to the host in between.
+    pub fn run<'proto>(&mut self, interns: &Interns<'proto>, host: &mut H, budget: usize) {
+        if let VM::Running(runner) = self {
+            let stopped = runner.run(interns, host, budget);
+            self.settle(stopped);
+        }
+    }
 
-        *self = match tar {
-            VM::Updating => { VM::Failed(Error::UpdatedWhileUpdating) }
-            VM::Running(running) => {
-                match running.update(interns) {
-                    Err(e) => { VM::Failed(e) }
-                    Ok(v) => { v }
+    // From now on the program can spend `fuel`. A suspended program starts running again.
+    pub fn refuel(&mut self, fuel: u64) {
+        match self {
+            VM::Running(runner) | VM::Waiting(runner) | VM::Yielded(runner, _) => { runner.fuel = fuel; }
+            VM::Suspended(_) => {
+                if let VM::Suspended(mut runner) = mem::replace(self, VM::Failed(Error::NoMoreFrames)) {
+                    runner.fuel = fuel;
+                    *self = VM::Running(runner);
                 }
             }
-            VM::Succeeded(succeeded, vars) => { VM::Succeeded(succeeded, vars) }
-            VM::Failed(failed) => { VM::Failed(failed) }
+            _ => {}
+        }
+    }
+
+    // the yield the program stopped on evaluates to `answer`, and it carries on from there
+    pub fn resume(&mut self, answer: Value) {
+        if let VM::Yielded(..) = self {
+            if let VM::Yielded(mut runner, _) = mem::replace(self, VM::Failed(Error::NoMoreFrames)) {
+                runner.push(answer);
+                *self = VM::Running(runner);
+            }
+        }
+    }
+
+    // a waiting program goes back to its receive
+    pub fn wake(&mut self) {
+        if let VM::Waiting(_) = self {
+            if let VM::Waiting(runner) = mem::replace(self, VM::Failed(Error::NoMoreFrames)) {
+                *self = VM::Running(runner);
+            }
+        }
+    }
+
+    // gives a fresh program its place in a scheduler
+    pub fn enroll(&mut self, pid: Pid, post: Rc<RefCell<PostOffice>>) {
+        if let VM::Running(runner) = self { runner.post = Some((pid, post)); }
+    }
+
+    // what `get` and `put` read and write from now on. the puts only reach it if the run succeeds,
+    // and the host keeps a handle too, so they're still there for the next VM it hands the store to
+    pub fn use_store(&mut self, store: Rc<RefCell<Store>>) {
+        match self {
+            VM::Running(runner) | VM::Suspended(runner) | VM::Waiting(runner) | VM::Yielded(runner, _) => {
+                runner.store = Some(store);
+            }
+            _ => {}
+        }
+    }
+
+    fn settle(&mut self, stopped: Runtime<Option<Stop>>) {
+        match stopped {
+            Ok(None) => {}
+            Ok(Some(Stop::Succeeded(value, vars))) => {
+                // only now does anything the run put reach the store
+                if let VM::Running(runner) = self { runner.commit(); }
+                *self = VM::Succeeded(value, vars);
+            }
+            Ok(Some(Stop::OutOfFuel)) => {
+                // the runner has to move from one variant to the other, so something holds its place
+                if let VM::Running(runner) = mem::replace(self, VM::Failed(Error::NoMoreFrames)) {
+                    *self = VM::Suspended(runner);
+                }
+            }
+            Ok(Some(Stop::Waiting)) => {
+                if let VM::Running(runner) = mem::replace(self, VM::Failed(Error::NoMoreFrames)) {
+                    *self = VM::Waiting(runner);
+                }
+            }
+            Ok(Some(Stop::Yielded(value))) => {
+                if let VM::Running(runner) = mem::replace(self, VM::Failed(Error::NoMoreFrames)) {
+                    *self = VM::Yielded(runner, value);
+                }
+            }
+            Err(e) => { *self = VM::Failed(e); }
+        }
+    }
+
+    // succeeded or failed: nothing more will happen to it
+    pub fn is_done(&self) -> bool {
+        match self {
+            VM::Succeeded(..) | VM::Failed(_) => { true }
+            _ => { false }
         }
     }
 
@@ -69,3 +190,266 @@ impl<'a> VM<'a> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::runtime::NativeError;
+    use crate::interns::Intern;
+    use crate::irs::executable1::{FFIProcedure, NativeStep};
+    use crate::library::Library;
+    use crate::primitive::Functor;
+    use crate::testing::{call_with, compile_library};
+
+    // `half(n)`, for even n only, `count(n)`, which adds n to the host's total,
+    // and `twice(f, x)`, which is `call f(call f(x))`
+    struct Natives;
+
+    // `f` with `x` added to its args, as `call` does
+    fn extend(f: &Value, x: Value) -> Value {
+        match f.clone() {
+            Value::Compound(head, mut args) => {
+                Rc::make_mut(&mut args).push(x);
+                Value::Compound(head, args)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    impl Library<i64> for Natives {
+        fn add_functions(&self, interns: &mut Interns, procedures: &mut HashMap<Functor<Intern>, FFIProcedure<i64>>) {
+            procedures.insert(Functor(interns.intern("half"), 1), FFIProcedure::Native(box |_, _, _, call| {
+                match call {
+                    Value::Compound(_, args) => match args[0] {
+                        Value::Integer(i) if i % 2 == 0 => Ok(NativeStep::Done(Value::Integer(i / 2))),
+                        _ => Err(NativeError::new("odd")),
+                    },
+                    _ => unreachable!(),
+                }
+            }));
+            procedures.insert(Functor(interns.intern("count"), 1), FFIProcedure::Native(box |_, _, total, call| {
+                match call {
+                    Value::Compound(_, args) => match args[0] {
+                        Value::Integer(i) => { *total += i; Ok(NativeStep::Done(Value::Integer(*total))) }
+                        _ => Err(NativeError::new("not a number")),
+                    },
+                    _ => unreachable!(),
+                }
+            }));
+            procedures.insert(Functor(interns.intern("twice"), 2), FFIProcedure::Native(box |_, _, _, call| {
+                let (f, x) = match call {
+                    Value::Compound(_, args) => (args[0].clone(), args[1].clone()),
+                    _ => unreachable!(),
+                };
+                Ok(NativeStep::Call(extend(&f, x), box move |_, _, _, once| {
+                    Ok(NativeStep::Call(extend(&f, once), box |_, _, _, again| {
+                        match again {
+                            Value::Integer(_) => Ok(NativeStep::Done(again)),
+                            _ => Err(NativeError::new("expected a number back")),
+                        }
+                    }))
+                }))
+            }));
+        }
+    }
+
+    #[test]
+    fn native_errors() {
+        let src = "
+            pub fn good { ret call half(10). }
+            pub fn bad { let @x = call half(3). ret @x. }
+            pub fn tail { ret call half(5). }
+        ";
+        let (interns, code) = compile_library(&[src], Natives).unwrap();
+        let mut total = 0;
+        assert_eq!(call_with(&interns, &code, &mut total, "good"), "5");
+        let half = Functor(interns.to_intern("half").unwrap(), 1);
+        for entry in &["bad", "tail"] {
+            let call = Value::compound(interns.to_intern(entry).unwrap(), vec![]);
+            let mut vm = VM::go(&interns, &code, &mut total, call).unwrap();
+            vm.run(&interns, &mut total, usize::MAX);
+            match vm {
+                VM::Failed(Error::Native { functor, message, .. }) => {
+                    assert_eq!(functor, half);
+                    assert_eq!(message, "odd");
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+        // the host calling a native directly
+        match VM::go(&interns, &code, &mut total, Value::compound(half.0, vec![Value::Integer(1)])) {
+            Err(Error::Native { call, .. }) => assert_eq!(call, Value::compound(half.0, vec![Value::Integer(1)])),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn host() {
+        let src = "pub fn main { let @a = call count(2). let @b = call count(3). ret v[@a, @b, call count(0)]. }";
+        let (interns, code) = compile_library(&[src], Natives).unwrap();
+        let mut total = 10;
+        assert_eq!(call_with(&interns, &code, &mut total, "main"), "v[12, 15, 15]");
+        assert_eq!(total, 15);
+        // the same host, still
+        assert_eq!(call_with(&interns, &code, &mut total, "main"), "v[17, 20, 20]");
+    }
+
+    #[test]
+    fn continuations() {
+        let src = "
+            fn add(@n, @x) { ret @x + @n. }
+            fn counted(@x) { ret call count(@x). }
+            pub fn body { let @y = call twice(add(3), 1). ret v[@y, call twice(fn(@x) { ret @x * @x. }, 3)]. }
+            pub fn tail { ret call twice(add(10), 0). }
+            pub fn nested { ret call twice(fn(@x) { ret call twice(add(1), @x). }, 0). }
+            pub fn host { ret call twice(counted, 5). }
+            pub fn bad { ret call twice(fn(@x) { ret v[]. }, 0). }
+            pub fn failing { ret call twice(fn(@x) { let 1 = 2. ret 0. }, 0). }
+        ";
+        let (interns, code) = compile_library(&[src], Natives).unwrap();
+        let mut total = 0;
+        assert_eq!(call_with(&interns, &code, &mut total, "body"), "v[7, 81]");
+        // a native in tail position has no frame of its own to come back to
+        assert_eq!(call_with(&interns, &code, &mut total, "tail"), "20");
+        assert_eq!(call_with(&interns, &code, &mut total, "nested"), "4");
+        // count(5) twice
+        assert_eq!(call_with(&interns, &code, &mut total, "host"), "10");
+        assert_eq!(total, 10);
+        assert!(call_with(&interns, &code, &mut total, "bad").contains("expected a number back"));
+        assert_eq!(call_with(&interns, &code, &mut total, "failing"), "failed: AssertionFailed");
+
+        // and straight from the host, as the outermost frame
+        let add = Value::compound(interns.to_intern("add").unwrap(), vec![Value::Integer(2)]);
+        let twice = Value::compound(interns.to_intern("twice").unwrap(), vec![add, Value::Integer(1)]);
+        let mut vm = VM::go(&interns, &code, &mut total, twice).unwrap();
+        // a slice at a time, so the continuation is picked up after running out of fuel
+        while !vm.is_done() {
+            vm.refuel(1);
+            vm.run(&interns, &mut total, usize::MAX);
+        }
+        match vm {
+            VM::Succeeded(Value::Integer(5), _) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    // the least fuel `call` finishes on without being suspended
+    fn fuel_needed(interns: &Interns, code: &Executable1<i64>, call: Value) -> u64 {
+        (1..).find(|&fuel| {
+            let mut vm = VM::go(interns, code, &mut 0, call.clone()).unwrap();
+            vm.refuel(fuel);
+            vm.run(interns, &mut 0, usize::MAX);
+            vm.is_done()
+        }).unwrap()
+    }
+
+    #[test]
+    fn fuel() {
+        let src = "
+            fn down(@n) { if @n == 0 { ret done. } ret call down(@n - 1). }
+            pub fn main { ret call down(100). }
+            pub fn six(@x) { let @v = v[@x, @x, @x, @x, @x, @x]. ret ok. }
+            pub fn twelve(@x) { let @v = v[@x, @x, @x, @x, @x, @x, @x, @x, @x, @x, @x, @x]. ret ok. }
+        ";
+        let (interns, code) = compile_library(&[src], Natives).unwrap();
+        let main = Value::compound(interns.to_intern("main").unwrap(), vec![]);
+
+        let mut vm = VM::go(&interns, &code, &mut 0, main).unwrap();
+        vm.refuel(10);
+        vm.run(&interns, &mut 0, usize::MAX);
+        match vm { VM::Suspended(_) => {} ref other => panic!("{:?}", other) }
+        let mut refuels = 1;
+        while !vm.is_done() {
+            vm.refuel(10);
+            vm.run(&interns, &mut 0, usize::MAX);
+            refuels += 1;
+        }
+        assert!(refuels > 10);
+        match vm { VM::Succeeded(value, _) => assert_eq!(crate::testing::show(&interns, &value), "done"), other => panic!("{:?}", other) }
+
+        // six more values in the vector: six more instructions to get them, and six more for the vector to hold them
+        let with = |name| Value::compound(interns.to_intern(name).unwrap(), vec![Value::Integer(1)]);
+        let (six, twelve) = (fuel_needed(&interns, &code, with("six")), fuel_needed(&interns, &code, with("twelve")));
+        assert_eq!(twelve - six, 12);
+    }
+
+    #[test]
+    fn run_is_many_updates() {
+        let src = "
+            fn fib(@n) { if @n < 2 { ret @n. } ret call fib(@n - 1) + call fib(@n - 2). }
+            fn build(@n, @acc) { if @n == 0 { ret @acc. } push @acc, call count(@n). ret call build(@n - 1, @acc). }
+            pub fn main {
+                let @v = call build(5, v[]).
+                ret v[call sort_by(@v, fn(@a, @b) { ret @a < @b. }), call fib(10)].
+            }
+        ";
+        let (interns, code) = compile_library(&[src], Natives).unwrap();
+        let main = Value::compound(interns.to_intern("main").unwrap(), vec![]);
+
+        // one instruction at a time
+        let mut total = 0;
+        let mut stepped = VM::go(&interns, &code, &mut total, main.clone()).unwrap();
+        let mut steps = 0;
+        while stepped.is_running() {
+            stepped.update(&interns, &mut total);
+            steps += 1;
+        }
+        let expected = (crate::testing::finished(&interns, stepped), total);
+        assert_eq!(expected.0, "v[v[5, 9, 12, 14, 15], 55]");
+        assert!(steps > 100);
+
+        for budget in &[1, 2, 7, 100, usize::MAX] {
+            let mut total = 0;
+            let mut vm = VM::go(&interns, &code, &mut total, main.clone()).unwrap();
+            while vm.is_running() { vm.run(&interns, &mut total, *budget); }
+            assert_eq!((crate::testing::finished(&interns, vm), total), expected, "budget {}", budget);
+        }
+    }
+
+    #[test]
+    fn private_procedures() {
+        let lib = "
+            module lib.
+            fn secret(@x) { ret @x * 2. }
+            pub fn open(@x) { let @y = call secret(@x). ret @y. }
+        ";
+        let main = "
+            import lib.
+            fn helper(@x) { ret @x + 1. }
+            pub fn main { let @a = call lib::secret(1). let @b = call lib::open(2). ret v[@a, @b, call helper(0)]. }
+            pub fn tail { ret call lib::secret(5). }
+        ";
+        let (interns, code) = compile_library(&[main, lib], Natives).unwrap();
+        let go = |name: &str, args: Vec<Value>| -> Runtime<String> {
+            let call = Value::compound(interns.to_intern(name).unwrap(), args);
+            let mut vm = VM::go(&interns, &code, &mut 0, call)?;
+            while vm.is_running() { vm.run(&interns, &mut 0, usize::MAX); }
+            Ok(crate::testing::finished(&interns, vm))
+        };
+
+        // `pub` is about what the host can call: any procedure can call any other, from any module
+        assert_eq!(go("main", vec![]).unwrap(), "v[2, 4, 1]");
+        // including as a tail call, which reuses the public procedure's frame
+        assert_eq!(go("tail", vec![]).unwrap(), "10");
+        assert_eq!(go("lib::open", vec![Value::Integer(3)]).unwrap(), "6");
+
+        for (name, args) in vec![("lib::secret", vec![Value::Integer(1)]), ("helper", vec![Value::Integer(1)])] {
+            match go(name, args) {
+                Err(Error::NotPublic) => {}
+                other => panic!("{}: {:?}", name, other),
+            }
+        }
+
+        // the repl is the host too, whether it keeps the result or returns it
+        let repl = |src: &str| {
+            let mut interns = interns.extend();
+            let (vars, proc) = crate::parser::parse_repl_statement(src).unwrap().compile_repl(&mut interns, &code).unwrap();
+            let mut vm = VM::start_repl(&proc, &code, &vars, &mut HashMap::new());
+            while vm.is_running() { vm.run(&interns, &mut 0, usize::MAX); }
+            crate::testing::finished(&interns, vm)
+        };
+        assert_eq!(repl("let @x = call lib::open(1)."), "1");
+        assert_eq!(repl("let @x = call lib::secret(1)."), "failed: NotPublic");
+        assert_eq!(repl("ret call lib::secret(1)."), "failed: NotPublic");
+    }
+}
@@ -1,42 +1,114 @@
-use super::VM;
-use super::stackframe::*;
+use super::Stop;
+use super::stack::*;
 
 use crate::errors::runtime::*;
 use crate::interns::Interns;
-use crate::irs::executable1::{Executable1, FFIProcedure};
+use crate::irs::executable1::{Executable1, FFIProcedure, NativeStep};
 use crate::irs::instruction2::Instruction2;
 use crate::irs::procedure2::Procedure2;
-use crate::primitive::{Functor, Operand, Value};
+use crate::primitive::{unshare, Functor, Operand, Pid, Value};
+use crate::scheduler::PostOffice;
+use crate::store::Store;
 
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::collections::btree_set::BTreeSet;
 use std::iter::FromIterator;
+use std::rc::Rc;
 
 
 #[derive(Debug)]
-pub struct Runner<'a> {
-    // code, frames
+pub struct Runner<'a, H = ()> {
+    // code, frames, slots
     // short names for terse implementations
-    pub c: &'a Executable1,
-    pub f: Vec<StackFrame<'a>>,
+    pub c: &'a Executable1<H>,
+    pub f: Vec<Frame<'a>>,
+    // every frame's locals and then its operands, one frame after another (see stack.rs)
+    pub s: Vec<Option<Value>>,
+    // natives waiting on calls they made, innermost last
+    pub natives: Vec<NativeFrame<H>>,
+
+    // frames that belong to the host (the repl's, for instance):
+    // calls made from them can only enter public procedures
+    pub outside: usize,
+
+    // what's left to spend (see `cost`): at zero the runner stops, and picks up where it was once refueled
+    pub fuel: u64,
+
+    // this process's pid and its scheduler's post office, if it's running in one
+    pub post: Option<(Pid, Rc<RefCell<PostOffice>>)>,
+
+    // what `get` and `put` use, if the host gave it one
+    pub store: Option<Rc<RefCell<Store>>>,
+    // what the program has put, which only goes in the store once it succeeds:
+    // a program that fails (or is dropped before it finishes) never changed anything
+    pub writes: BTreeMap<Value, Value>,
+    pub atomics: Vec<Atomic>,
 }
 
 
-impl<'a> Runner<'a> {
-    pub fn new(c: &Executable1) -> Runner {
-        Runner {c, f: vec![]}
+impl<'a, H> Runner<'a, H> {
+    pub fn new(c: &'a Executable1<H>) -> Self {
+        Runner {c, f: vec![], s: vec![], natives: vec![], outside: 0, fuel: u64::MAX, post: None, store: None, writes: BTreeMap::new(), atomics: vec![]}
     }
 
-    pub fn call(mut self, interns: &Interns, call: Value) -> Runtime<VM<'a>> {
+    // Runs until the program stops or `budget` instructions have gone by,
+    // whichever is first. Ok(None) means there's more to do.
+    pub fn run(&mut self, interns: &Interns, host: &mut H, budget: usize) -> Runtime<Option<Stop>> {
+        for _ in 0..budget {
+            if let Some(stop) = self.update(interns, host)? { return Ok(Some(stop)); }
+        }
+        Ok(None)
+    }
+
+    pub fn call(&mut self, interns: &Interns, host: &mut H, call: Value) -> Runtime<Option<Stop>> {
+        let from_outside = self.f.len() <= self.outside;
+        self.call_from(interns, host, call, from_outside)
+    }
+
+    // the first call of a process the program spawned itself, so it can be to a private procedure
+    pub fn call_spawned(&mut self, interns: &Interns, host: &mut H, call: Value) -> Runtime<Option<Stop>> {
+        self.call_from(interns, host, call, false)
+    }
+
+    fn call_from(&mut self, interns: &Interns, host: &mut H, call: Value, from_outside: bool) -> Runtime<Option<Stop>> {
         let c: &Procedure2 = match &call {
             Value::Compound(intern, args) => {
                 match self.c.procedures.get(&Functor(*intern, args.len())) {
                     None => { return Err(Error::NoSuchProcedure); }
-                    Some(FFIProcedure::Dynamic(c)) => { c }
+                    Some(FFIProcedure::Dynamic(c)) => {
+                        if !c.public && from_outside { return Err(Error::NotPublic); }
+                        c
+                    }
                     Some(FFIProcedure::Native(native)) => {
-                        let sp = self.f.len() - 1;
-                        self.f[sp].push(native(interns, &self.c, call));
+                        let step = call_native(interns, &self.c, host, native, &call)?;
+                        return self.step_native(interns, host, call, step);
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::CallNotCompound);
+            }
+        };
+
 
-                        return Ok(VM::Running(self));
+        self.enter(c);
+        self.push(call);
+        Ok(None)
+    }
+
+    fn tail_call(&mut self, interns: &Interns, host: &mut H, sp: usize, call: Value) -> Runtime<Option<Stop>> {
+        let c: &Procedure2 = match &call {
+            Value::Compound(intern, args) => {
+                match self.c.procedures.get(&Functor(*intern, args.len())) {
+                    None => { return Err(Error::NoSuchProcedure); }
+                    Some(FFIProcedure::Dynamic(c)) => { c }
+                    Some(FFIProcedure::Native(native)) => {
+                        // no frame to reuse: whatever the native comes to is what this frame returns
+                        let step = call_native(interns, &self.c, host, native, &call)?;
+                        if let NativeStep::Done(value) = step { return self.ret(interns, host, value); }
+                        self.leave();
+                        return self.step_native(interns, host, call, step);
                     }
                 }
             }
@@ -45,75 +117,190 @@ impl<'a> Runner<'a> {
             }
         };
 
+        // the caller's slots are already where the callee's should go
+        self.end_atomics(self.f.len());
+        self.s.truncate(self.f[sp].base);
+        self.s.resize(self.f[sp].base + c.vars, None);
+        self.f[sp] = Frame { c, ip: 0, base: self.f[sp].base };
+        self.push(call);
+        Ok(None)
+    }
 
-        let mut f = StackFrame::new_on(c);
-        f.push(call);
-        self.f.push(f);
-        Ok(VM::Running(self))
+    fn ret(&mut self, interns: &Interns, host: &mut H, value: Value) -> Runtime<Option<Stop>> {
+        if self.f.len() == 1 && self.natives.is_empty() {
+            // the host gets the last frame's locals
+            let top = self.f.pop().unwrap();
+            self.s.truncate(top.base + top.c.vars);
+            return Ok(Some(Stop::Succeeded(value, self.s.split_off(top.base))));
+        }
+        self.leave();
+        self.deliver(interns, host, value)
+    }
+
+    // `value` is what a native came to, or what the frame that was on top returned:
+    // a native waiting on it gets it first, otherwise it goes to the top frame
+    fn deliver(&mut self, interns: &Interns, host: &mut H, value: Value) -> Runtime<Option<Stop>> {
+        match self.natives.last() {
+            Some(native) if native.depth == self.f.len() => {
+                let NativeFrame { call, then, .. } = self.natives.pop().unwrap();
+                let step = then(interns, self.c, host, value).map_err(|e| native_error(e, call.clone()))?;
+                self.step_native(interns, host, call, step)
+            }
+            _ if self.f.is_empty() => Ok(Some(Stop::Succeeded(value, vec![]))),
+            _ => {
+                self.push(value);
+                Ok(None)
+            }
+        }
     }
 
-    pub fn update(mut self, interns: &Interns) -> Runtime<VM<'a>> {
+    fn step_native(&mut self, interns: &Interns, host: &mut H, call: Value, step: NativeStep<H>) -> Runtime<Option<Stop>> {
+        match step {
+            NativeStep::Done(value) => self.deliver(interns, host, value),
+            NativeStep::Call(callee, then) => {
+                self.natives.push(NativeFrame { depth: self.f.len(), call, then });
+                // the callee is usually something the program handed the native, like a lambda
+                self.call_from(interns, host, callee, false)
+            }
+        }
+    }
+
+    // a single instruction
+    pub fn update(&mut self, interns: &Interns, host: &mut H) -> Runtime<Option<Stop>> {
         let sp = self.f.len() - 1;
         let ip = self.f[sp].ip;
+        let base = self.f[sp].base;
 
         if ip >= self.f[sp].c.instructions.len() { return Err(Error::OutOfCode); }
+        let inst = self.f[sp].c.instructions[ip];
+        // checked before anything happens, so the instruction runs in full after a refuel.
+        // the last one may cost more than is left: otherwise one that costs more than a
+        // whole refuel would never run
+        if self.fuel == 0 { return Ok(Some(Stop::OutOfFuel)); }
+        self.fuel = self.fuel.saturating_sub(cost(inst));
         self.f[sp].ip += 1;
 
         use Instruction2::*;
-        match self.f[sp].c.instructions[ip] {
+        match inst {
             Push(Operand::Integer(i)) => {
-                self.f[sp].push(Value::Integer(i));
-                Ok(VM::Running(self))
+                self.push(Value::Integer(i));
+                Ok(None)
             }
 
             Push(Operand::Bool(b)) => {
-                self.f[sp].push(Value::Bool(b));
-                Ok(VM::Running(self))
+                self.push(Value::Bool(b));
+                Ok(None)
+            }
+
+            Push(Operand::Const(ix)) => {
+                let value = self.c.constants[ix].clone();
+                self.push(value);
+                Ok(None)
             }
 
             Set(vp) => {
-                let s1 = self.f[sp].pop()?;
-                self.f[sp].v[vp.0] = Some(s1);
-                Ok(VM::Running(self))
+                let s1 = self.pop()?;
+                self.s[base + vp.0] = Some(s1);
+                Ok(None)
             }
             SetAssert(vp) => {
                 // ZEKKA NOTE: Consider dropping this instruction, replace it with setassert
-                let s1 = self.f[sp].pop()?;
-                match &self.f[sp].v[vp.0] {
+                let s1 = self.pop()?;
+                match &self.s[base + vp.0] {
                     None => {
-                        self.f[sp].v[vp.0] = Some(s1);
-                        Ok(VM::Running(self))
+                        self.s[base + vp.0] = Some(s1);
+                        Ok(None)
                     }
                     Some(x) => {
-                        if x == &s1 { Ok(VM::Running(self)) }
+                        if x == &s1 { Ok(None) }
                         else { Err(Error::SetAssertFailed) }
                     }
                 }
             }
             Get(vp) => {
-                let to_push = match &self.f[sp].v[vp.0] {
+                let to_push = match &self.s[base + vp.0] {
                     None => { return Err(Error::GetUnset) }
                     Some(x) => { x.clone() }
                 };
-                self.f[sp].push(to_push);
-                Ok(VM::Running(self))
+                self.push(to_push);
+                Ok(None)
+            }
+
+            Take(vp) => {
+                let to_push = match self.s[base + vp.0].take() {
+                    None => { return Err(Error::GetUnset) }
+                    Some(x) => { x }
+                };
+                self.push(to_push);
+                Ok(None)
+            }
+
+            // make_mut only copies if another value shares the vector, so nothing else sees the change
+            VecPush(vp) => {
+                let x = self.pop()?;
+                match &mut self.s[base + vp.0] {
+                    None => { return Err(Error::GetUnset) }
+                    Some(Value::Vector(xs)) => { Rc::make_mut(xs).push(x); }
+                    Some(_) => { return Err(Error::InPlaceWrongType) }
+                }
+                Ok(None)
+            }
+            VecPop(vp) => {
+                let x = match &mut self.s[base + vp.0] {
+                    None => { return Err(Error::GetUnset) }
+                    Some(Value::Vector(xs)) => match Rc::make_mut(xs).pop() {
+                        None => { return Err(Error::PopEmpty) }
+                        Some(x) => x,
+                    },
+                    Some(_) => { return Err(Error::InPlaceWrongType) }
+                };
+                self.push(x);
+                Ok(None)
+            }
+            VecSet(vp) => {
+                let x = self.pop()?;
+                let ix = match self.pop()? {
+                    Value::Integer(ix) => ix,
+                    _ => { return Err(Error::NotAnIndex) }
+                };
+                match &mut self.s[base + vp.0] {
+                    None => { return Err(Error::GetUnset) }
+                    Some(Value::Vector(xs)) => {
+                        if ix < 0 || ix as usize >= xs.len() { return Err(Error::IndexOutOfRange) }
+                        Rc::make_mut(xs)[ix as usize] = x;
+                    }
+                    Some(_) => { return Err(Error::InPlaceWrongType) }
+                }
+                Ok(None)
+            }
+            CompoundSetArg(vp, ix) => {
+                let x = self.pop()?;
+                match &mut self.s[base + vp.0] {
+                    None => { return Err(Error::GetUnset) }
+                    Some(Value::Compound(_, args)) => {
+                        if ix >= args.len() { return Err(Error::IndexOutOfRange) }
+                        Rc::make_mut(args)[ix] = x;
+                    }
+                    Some(_) => { return Err(Error::InPlaceWrongType) }
+                }
+                Ok(None)
             }
 
             Assert => {
-                let s1 = self.f[sp].pop()?;
+                let s1 = self.pop()?;
                 match s1 {
                     Value::Bool(true) => {},
                     Value::Bool(false) => { return Err(Error::AssertionFailed); }
                     _ => return Err(Error::ConditionalWrongType)
                 }
-                Ok(VM::Running(self))
+                Ok(None)
             },
             Jump(new_ip) => {
                 self.f[sp].ip = new_ip.0;
-                Ok(VM::Running(self))
+                Ok(None)
             }
             JumpNo(new_ip) => {
-                let s1 = self.f[sp].pop()?;
+                let s1 = self.pop()?;
                 match s1 {
                     Value::Bool(true) => {},
                     Value::Bool(false) => {
@@ -121,30 +308,117 @@ impl<'a> Runner<'a> {
                     }
                     _ => return Err(Error::ConditionalWrongType)
                 };
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Pop => {
-                self.f[sp].pop()?;
-                Ok(VM::Running(self))
+                self.pop()?;
+                Ok(None)
             }
             Ret => {
-                let s1 = self.f[sp].pop()?;
-                let top = self.f.pop();
+                let s1 = self.pop()?;
+                self.ret(interns, host, s1)
+            }
+            Call => {
+                let call = self.pop()?;
+                self.call(interns, host, call)
+            }
+            TailCall => {
+                let call = self.pop()?;
+                self.tail_call(interns, host, sp, call)
+            }
+            NoClauseMatched => {
+                let call = self.pop()?;
+                Err(Error::NoClauseMatched(call))
+            }
 
-                if self.f.len() == 0 {
-                    return Ok(VM::Succeeded(s1, top.unwrap().v));
+            Yield => {
+                let value = self.pop()?;
+                Ok(Some(Stop::Yielded(value)))
+            }
+
+            Spawn => {
+                let call = self.pop()?;
+                let (_, post) = self.post()?;
+                let pid = post.borrow_mut().spawn(call);
+                self.push(Value::Pid(pid));
+                Ok(None)
+            }
+            SelfPid => {
+                let (me, _) = self.post()?;
+                self.push(Value::Pid(me));
+                Ok(None)
+            }
+            Send => {
+                let message = self.pop()?;
+                let to = match self.pop()? {
+                    Value::Pid(to) => to,
+                    _ => { return Err(Error::SendNotPid) }
+                };
+                let (_, post) = self.post()?;
+                post.borrow_mut().send(to, message);
+                Ok(None)
+            }
+            ReceiveNext => {
+                let (me, post) = self.post()?;
+                let next = post.borrow_mut().next(me);
+                match next {
+                    Some(message) => { self.push(message); }
+                    None => {
+                        // try again from here once something arrives
+                        self.f[sp].ip = ip;
+                        return Ok(Some(Stop::Waiting));
+                    }
                 }
-                self.f[sp - 1].push(s1);
-                Ok(VM::Running(self))
+                Ok(None)
             }
-            Call => {
-                let call = self.f[sp].pop()?;
-                self.call(interns, call)
+            ReceiveAccept => {
+                let (me, post) = self.post()?;
+                post.borrow_mut().accept(me);
+                Ok(None)
+            }
+
+            StoreGet => {
+                let key = self.pop()?;
+                let value = self.lookup(&self.store()?.borrow(), &key);
+                match value {
+                    Some(value) => { self.push(value); }
+                    None => { return Err(Error::NotInStore(key)) }
+                }
+                Ok(None)
+            }
+            StoreGetOr => {
+                let start = self.pop()?;
+                let key = self.pop()?;
+                let value = self.lookup(&self.store()?.borrow(), &key);
+                self.push(value.unwrap_or(start));
+                Ok(None)
+            }
+            StorePut => {
+                let value = self.pop()?;
+                let key = self.pop()?;
+                self.store()?;
+                self.writes_mut().insert(key, value);
+                Ok(None)
+            }
+
+            Savepoint => {
+                let atomic = Atomic { depth: self.f.len(), writes: BTreeMap::new() };
+                self.atomics.push(atomic);
+                Ok(None)
+            }
+            Release => {
+                if self.atomics.is_empty() { return Err(Error::NotAtomic); }
+                self.release();
+                Ok(None)
+            }
+            Rollback => {
+                self.atomics.pop().ok_or(Error::NotAtomic)?;
+                Ok(None)
             }
 
             Mark(mark_ip, keep_on_failure) => {
-                let value = self.f[sp].pop()?;
+                let value = self.pop()?;
 
                 self.destructure(sp, ip + 1, mark_ip.0, keep_on_failure, value)
             }
@@ -154,191 +428,217 @@ impl<'a> Runner<'a> {
             }
 
             DestructCompound(f) => {
-                let s1 = self.f[sp].pop()?;
+                let s1 = self.pop()?;
                 match s1 {
-                    Value::Compound(intern, mut args) if intern == f.0 && args.len() == f.1 => {
-                        for arg in args.drain(..).rev() {
-                            self.f[sp].push(arg);
+                    Value::Compound(intern, args) if intern == f.0 && args.len() == f.1 => {
+                        for arg in unshare(args).into_iter().rev() {
+                            self.push(arg);
                         }
-                        Ok(VM::Running(self))
+                        Ok(None)
                     }
                     _ => { Err(Error::DestructWrongType) }
                 }
             },
             DestructVector(sz) => {
-                let s1 = self.f[sp].pop()?;
+                let s1 = self.pop()?;
                 match s1 {
-                    Value::Vector(mut args) if args.len() == sz => {
-                        for arg in args.drain(..).rev() {
-                            self.f[sp].push(arg);
+                    Value::Vector(args) if args.len() == sz => {
+                        for arg in unshare(args).into_iter().rev() {
+                            self.push(arg);
                         }
-                        Ok(VM::Running(self))
+                        Ok(None)
                     }
                     _ => { Err(Error::DestructWrongType) }
                 }
             },
             Destruct(sz) => {
-                let s1 = self.f[sp].pop()?;
+                let s1 = self.pop()?;
                 match s1 {
-                    Value::Compound(_, mut args) if args.len() == sz => {
-                        for arg in args.drain(..).rev() {
-                            self.f[sp].push(arg);
+                    Value::Compound(_, args) if args.len() == sz => {
+                        for arg in unshare(args).into_iter().rev() {
+                            self.push(arg);
                         }
-                        Ok(VM::Running(self))
+                        Ok(None)
                     }
-                    Value::Vector(mut args) if args.len() == sz => {
-                        for arg in args.drain(..).rev() {
-                            self.f[sp].push(arg);
+                    Value::Vector(args) if args.len() == sz => {
+                        for arg in unshare(args).into_iter().rev() {
+                            self.push(arg);
                         }
-                        Ok(VM::Running(self))
+                        Ok(None)
                     }
                     // A set can't be destructed (because its order is arbitrary)
                     _ => { Err(Error::DestructWrongType) }
                 }
             }
             ConstructCompound(f) => {
-                let len = self.f[sp].s.len();
-                if len < f.1 { return Err(Error::NoMoreValues); }
-                let values = self.f[sp].s.drain((len - f.1)..len).collect::<Vec<Value>>();
-                self.f[sp].push(Value::Compound(f.0, values));
-                Ok(VM::Running(self))
+                let values = self.pop_n(f.1)?;
+                self.push(Value::compound(f.0, values));
+                Ok(None)
             },
+            ExtendCompound(n) => {
+                let values = self.pop_n(n)?;
+                match self.pop()? {
+                    Value::Compound(intern, mut args) => {
+                        Rc::make_mut(&mut args).extend(values);
+                        self.push(Value::Compound(intern, args));
+                        Ok(None)
+                    }
+                    _ => { Err(Error::ExtendNotCompound) }
+                }
+            }
             ConstructVector(sz) => {
-                let len = self.f[sp].s.len();
-                if len < sz { return Err(Error::NoMoreValues); }
-                let values = self.f[sp].s.drain((len - sz)..len).collect::<Vec<Value>>();
-                self.f[sp].push(Value::Vector(values));
-                Ok(VM::Running(self))
+                let values = self.pop_n(sz)?;
+                self.push(Value::vector(values));
+                Ok(None)
             }
             ConstructSet(sz) => {
-                let len = self.f[sp].s.len();
-                if len < sz { return Err(Error::NoMoreValues); }
-                let values = BTreeSet::from_iter(self.f[sp].s.drain((len - sz)..len));
-                self.f[sp].push(Value::Set(values));
-                Ok(VM::Running(self))
+                let values = BTreeSet::from_iter(self.pop_n(sz)?);
+                self.push(Value::set(values));
+                Ok(None)
             }
 
             Equals => {
-                let s1 = self.f[sp].pop()?;
-                let s2 = self.f[sp].pop()?;
-                self.f[sp].push(Value::Bool(s1 == s2));
-                Ok(VM::Running(self))
+                let s1 = self.pop()?;
+                let s2 = self.pop()?;
+                self.push(Value::Bool(s1 == s2));
+                Ok(None)
             }
 
             EqualsOperandAssert(Operand::Bool(b)) => {
-                let s1 = self.f[sp].pop()?;
+                let s1 = self.pop()?;
                 match s1 {
                     Value::Bool(b2) if b == b2 => { }
                     // TODO: Better error
                     _ => { return Err(Error::AssertionFailed) }
                 }
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             EqualsOperandAssert(Operand::Integer(i)) => {
-                let s1 = self.f[sp].pop()?;
+                let s1 = self.pop()?;
                 match s1 {
                     Value::Integer(i2) if i == i2 => { }
                     // TODO: Better error
                     _ => { return Err(Error::AssertionFailed) }
                 }
-                Ok(VM::Running(self))
+                Ok(None)
+            }
+
+            EqualsOperandAssert(Operand::Const(ix)) => {
+                let s1 = self.pop()?;
+                if s1 != self.c.constants[ix] { return Err(Error::AssertionFailed) }
+                Ok(None)
             }
 
             Mul => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Integer(i1 * i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Div => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Integer(i1 / i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Add => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Integer(i1 + i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Subtract => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Integer(i1 - i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Le => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Bool(i1 <= i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Ge => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Bool(i1 >= i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Lt => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Bool(i1 < i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Gt => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Bool(i1 > i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Eq => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Bool(i1 == i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
 
             Ne => {
-                let top = self.f[sp].pop_num2()?;
-                self.f[sp].push(match top {
+                let top = self.pop_num2()?;
+                self.push(match top {
                     Num2::Integer(i1, i2) => Value::Bool(i1 != i2)
                 });
-                Ok(VM::Running(self))
+                Ok(None)
             }
         }
     }
 
+    fn post(&self) -> Runtime<(Pid, Rc<RefCell<PostOffice>>)> {
+        match &self.post {
+            Some((me, post)) => Ok((*me, post.clone())),
+            None => Err(Error::NoScheduler),
+        }
+    }
+
+    fn store(&self) -> Runtime<Rc<RefCell<Store>>> {
+        match &self.store {
+            Some(store) => Ok(store.clone()),
+            None => Err(Error::NoStore),
+        }
+    }
+
     fn destructure(
-        mut self,
+        &mut self,
         sp: usize, ip: usize,
         else_ip: usize, keep_on_failure: bool, value: Value
-    ) -> Runtime<VM<'a>> {
+    ) -> Runtime<Option<Stop>> {
         // ZEKKA NOTE: Maybe add support for pushes.
 
         // ZEKKA NOTE: This can theoretically be shared between invocations
         // using set_len() to avoid cleanup/realloc
-        let mut temps: Vec<Option<&Value>> =
-            Vec::with_capacity(self.f[sp].v.len());
-        for _ in 0..self.f[sp].v.len() { temps.push(None); }
+        let base = self.f[sp].base;
+        let vars = self.f[sp].c.vars;
+        let mut temps: Vec<Option<&Value>> = Vec::with_capacity(vars);
+        for _ in 0..vars { temps.push(None); }
         let mut destructure_stack: Vec<&Value> = Vec::new();
         destructure_stack.push(&value);
 
@@ -349,7 +649,7 @@ impl<'a> Runner<'a> {
             if seek_ip > self.f[sp].c.instructions.len() { return Err(Error::OutOfCode); }
             match self.f[sp].c.instructions[seek_ip].clone() {
                 Unmark => { break; }
-                SetAssert(l) => { temps[l.0] = self.f[sp].v[l.0].as_ref(); }
+                SetAssert(l) => { temps[l.0] = self.s[base + l.0].as_ref(); }
                 Pop => { }
                 DestructCompound(_) => { }
                 DestructVector(_) => { }
@@ -441,6 +741,12 @@ impl<'a> Runner<'a> {
                         _ => { return self.destructure_fail(sp, else_ip, keep_on_failure, value) }
                     }
                 }
+                EqualsOperandAssert(Operand::Const(ix)) => {
+                    let s1 = nopt(destructure_stack.pop())?;
+                    if s1 != &self.c.constants[ix] {
+                        return self.destructure_fail(sp, else_ip, keep_on_failure, value)
+                    }
+                }
                 _ => { unreachable!() }
             }
             destructure_ip += 1;
@@ -455,10 +761,10 @@ impl<'a> Runner<'a> {
             match self.f[sp].c.instructions[write_ip].clone() {
                 Unmark => {
                     self.f[sp].ip = write_ip + 1;
-                    return Ok(VM::Running(self));
+                    return Ok(None);
                 }
                 SetAssert(l) => {
-                    self.f[sp].v[l.0] = Some(write_stack.pop().unwrap());
+                    self.s[base + l.0] = Some(write_stack.pop().unwrap());
                 }
                 Pop => {
                     write_stack.pop().unwrap();
@@ -466,8 +772,8 @@ impl<'a> Runner<'a> {
                 DestructCompound(_) => {
                     let s1 = write_stack.pop().unwrap();
                     match s1 {
-                        Value::Compound(_, mut args) => {
-                            for arg in args.drain(..).rev() {
+                        Value::Compound(_, args) => {
+                            for arg in unshare(args).into_iter().rev() {
                                 write_stack.push(arg);
                             }
                         }
@@ -477,8 +783,8 @@ impl<'a> Runner<'a> {
                 DestructVector(_) => {
                     let s1 = write_stack.pop().unwrap();
                     match s1 {
-                        Value::Vector(mut args) => {
-                            for arg in args.drain(..).rev() {
+                        Value::Vector(args) => {
+                            for arg in unshare(args).into_iter().rev() {
                                 write_stack.push(arg);
                             }
                         }
@@ -488,13 +794,13 @@ impl<'a> Runner<'a> {
                 Destruct(_) => {
                     let s1 = write_stack.pop().unwrap();
                     match s1 {
-                        Value::Compound(_, mut args) => {
-                            for arg in args.drain(..).rev() {
+                        Value::Compound(_, args) => {
+                            for arg in unshare(args).into_iter().rev() {
                                 write_stack.push(arg);
                             }
                         }
-                        Value::Vector(mut args) => {
-                            for arg in args.drain(..).rev() {
+                        Value::Vector(args) => {
+                            for arg in unshare(args).into_iter().rev() {
                                 write_stack.push(arg);
                             }
                         }
@@ -511,14 +817,44 @@ impl<'a> Runner<'a> {
     }
 
     fn destructure_fail(
-        mut self,
+        &mut self,
         sp: usize, else_ip: usize,
         keep_on_failure: bool, value: Value
-    ) -> Runtime<VM<'a>> {
+    ) -> Runtime<Option<Stop>> {
         self.f[sp].ip = else_ip;
         if keep_on_failure {
-            self.f[sp].push(value);
+            self.push(value);
         }
-        Ok(VM::Running(self))
+        Ok(None)
     }
-}
\ No newline at end of file
+}
+
+fn call_native<H>(
+    interns: &Interns, code: &Executable1<H>, host: &mut H,
+    native: &dyn Fn(&Interns, &Executable1<H>, &mut H, Value) -> Result<NativeStep<H>, NativeError>,
+    call: &Value,
+) -> Runtime<NativeStep<H>> {
+    native(interns, code, host, call.clone()).map_err(|e| native_error(e, call.clone()))
+}
+
+// a native that fails is reported along with the call that made it fail
+fn native_error(e: NativeError, call: Value) -> Error {
+    let functor = match &call {
+        Value::Compound(intern, args) => Functor(*intern, args.len()),
+        _ => unreachable!(), // natives are only found by functor
+    };
+    Error::Native { functor, message: e.message, call }
+}
+
+// One per instruction, plus one per value an instruction puts into a new term,
+// so a script can't dodge its limit by building huge terms in a few steps.
+fn cost(inst: Instruction2) -> u64 {
+    use Instruction2::*;
+    let values = match inst {
+        ConstructCompound(f) => f.1,
+        ConstructVector(n) | ConstructSet(n) | ExtendCompound(n) => n,
+        VecPush(_) | Send | Spawn | StorePut => 1,
+        _ => 0,
+    };
+    1 + values as u64
+}
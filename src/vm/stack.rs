@@ -0,0 +1,143 @@
+use super::runner::Runner;
+
+use crate::errors::runtime::*;
+use crate::irs::executable1::Continuation;
+use crate::irs::procedure2::Procedure2;
+use crate::primitive::Value;
+use crate::store::Store;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+// A frame doesn't own any values: its locals and then its operands live on the runner's
+// one stack of slots, starting at `base`. Only the top frame's operands can grow, so
+// a call is a few pushes instead of two allocations.
+#[derive(Debug)]
+pub struct Frame<'a> {
+    // code
+    pub c: &'a Procedure2,
+    pub ip: usize,
+    pub base: usize,
+}
+
+// A native waiting on a call it asked for (see NativeStep): the frame at `depth` is that call,
+// and when it returns, `then` gets the result in place of the frame below
+pub struct NativeFrame<H> {
+    pub depth: usize,
+    pub call: Value, // the native's own call, for errors
+    pub then: Continuation<H>,
+}
+
+impl<H> fmt::Debug for NativeFrame<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFrame {{ depth: {}, call: {:?} }}", self.depth, self.call)
+    }
+}
+
+// An `atomic` block that's being run: the frame running it, and what it's put so far,
+// on top of what was put outside it
+#[derive(Debug)]
+pub struct Atomic {
+    pub depth: usize,
+    pub writes: BTreeMap<Value, Value>,
+}
+
+impl<'a, H> Runner<'a, H> {
+    pub fn enter(&mut self, c: &'a Procedure2) {
+        let base = self.s.len();
+        self.s.resize(base + c.vars, None);
+        self.f.push(Frame { c, ip: 0, base });
+    }
+
+    // drops the top frame and everything on the stack for it
+    pub fn leave(&mut self) {
+        let depth = self.f.len();
+        let top = self.f.pop().unwrap();
+        self.s.truncate(top.base);
+        self.end_atomics(depth);
+    }
+
+    // atomic blocks that the frame at `depth` won't get to the end of (there was a `ret` in them):
+    // what they did is kept, as if they'd finished
+    pub fn end_atomics(&mut self, depth: usize) {
+        while self.atomics.last().map_or(false, |a| a.depth >= depth) {
+            self.release();
+        }
+    }
+
+    // the innermost atomic block's puts become its enclosing block's (or the whole run's)
+    pub fn release(&mut self) {
+        if let Some(atomic) = self.atomics.pop() {
+            self.writes_mut().extend(atomic.writes);
+        }
+    }
+
+    // where a put goes: the innermost atomic block's, if there is one
+    pub fn writes_mut(&mut self) -> &mut BTreeMap<Value, Value> {
+        match self.atomics.last_mut() {
+            Some(atomic) => &mut atomic.writes,
+            None => &mut self.writes,
+        }
+    }
+
+    // what the program would see under `key`: its own puts first, innermost block first, then the store's
+    pub fn lookup(&self, store: &Store, key: &Value) -> Option<Value> {
+        self.atomics.iter().rev().map(|a| &a.writes)
+            .chain(Some(&self.writes))
+            .find_map(|writes| writes.get(key))
+            .or_else(|| store.get(key))
+            .cloned()
+    }
+
+    // the program succeeded: everything it put goes in the store at once
+    pub fn commit(&mut self) {
+        self.end_atomics(0);
+        let writes = std::mem::replace(&mut self.writes, BTreeMap::new());
+        if let Some(store) = &self.store {
+            let mut store = store.borrow_mut();
+            for (key, value) in writes { store.put(key, value); }
+        }
+    }
+
+    // where the top frame's operands start
+    fn floor(&self) -> usize {
+        match self.f.last() {
+            None => 0,
+            Some(f) => f.base + f.c.vars,
+        }
+    }
+
+    pub fn push(&mut self, v: Value) {
+        self.s.push(Some(v))
+    }
+
+    pub fn pop(&mut self) -> Runtime<Value> {
+        if self.s.len() <= self.floor() { return Err(Error::NoMoreValues); }
+        match self.s.pop() {
+            Some(Some(x)) => Ok(x),
+            _ => Err(Error::NoMoreValues),
+        }
+    }
+
+    // the top n operands, bottom first
+    pub fn pop_n(&mut self, n: usize) -> Runtime<Vec<Value>> {
+        let len = self.s.len();
+        if len < self.floor() + n { return Err(Error::NoMoreValues); }
+        Ok(self.s.drain((len - n)..len).flatten().collect())
+    }
+
+    pub fn pop_num2(&mut self) -> Runtime<Num2> {
+        let n1 = self.pop()?;
+        let n2 = self.pop()?;
+        match (n1, n2) {
+            // RHS is on top
+            (Value::Integer(i1), Value::Integer(i2)) => Ok(Num2::Integer(i2, i1)),
+            _ => Err(Error::NotNumbers)
+        }
+    }
+}
+
+
+pub enum Num2 {
+    Integer(i64, i64),
+}